@@ -0,0 +1,101 @@
+//! Writing downloaded files into a zip archive.
+//!
+//! The archive is written to a `.part` file first, and only takes its final name once finished.
+
+use std::{
+    fs::{remove_file, rename, File},
+    io::{copy, BufWriter},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+/// A zip archive being written, which files can be added to from several threads.
+///
+/// All the methods block, so they should be called from [`tokio::task::spawn_blocking`].
+#[derive(Debug)]
+pub struct Archive {
+    path: PathBuf,
+    part_path: PathBuf,
+    writer: Mutex<ZipWriter<BufWriter<File>>>,
+}
+
+impl Archive {
+    /// Start writing an archive to be saved at `path`.
+    pub fn create(path: &Path) -> eyre::Result<Self> {
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+
+        let writer = ZipWriter::new(BufWriter::new(File::create(&part_path)?));
+        Ok(Self {
+            path: path.to_path_buf(),
+            part_path,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Copy the file at `path` into the archive as `name`, which uses `/` to separate folders.
+    pub fn add_file(&self, name: &str, path: &Path) -> eyre::Result<()> {
+        let mut file = File::open(path)?;
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(file.metadata()?.len() >= u32::MAX.into());
+
+        let mut writer = self.writer.lock().unwrap();
+        writer.start_file(name, options)?;
+        copy(&mut file, &mut *writer)?;
+        Ok(())
+    }
+
+    /// Finish writing the archive, and move it to its final path.
+    pub fn finish(self) -> eyre::Result<()> {
+        let writer = self.writer.into_inner().unwrap().finish()?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        rename(&self.part_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Stop writing the archive, and remove what was written so far.
+    pub fn discard(self) -> eyre::Result<()> {
+        drop(self.writer);
+        remove_file(&self.part_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Read as _};
+
+    use zip::ZipArchive;
+
+    use super::Archive;
+
+    #[test]
+    fn add_files() {
+        let dir = std::env::temp_dir().join(format!("mooc-pdf-download-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes.pdf");
+        fs::write(&file, b"%PDF-1.7").unwrap();
+
+        let path = dir.join("course.zip");
+        let archive = Archive::create(&path).unwrap();
+        archive.add_file("第一周/notes.pdf", &file).unwrap();
+        archive.finish().unwrap();
+
+        let mut zip = ZipArchive::new(fs::File::open(&path).unwrap()).unwrap();
+        let mut content = Vec::new();
+        zip.by_name("第一周/notes.pdf")
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, b"%PDF-1.7");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,41 @@
-use std::sync::RwLock;
+use std::{
+    borrow::Cow,
+    fs::{create_dir_all, File, OpenOptions},
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::SystemTime,
+};
 
 use bytes::Bytes;
 use cookie::{Cookie, ParseError};
-use cookie_store::CookieStore as CookieStoreImpl;
+use cookie_store::{serde::json, CookieDomain, CookieExpiration, CookieStore as CookieStoreImpl};
 use reqwest::{cookie::CookieStore, header::HeaderValue, Url};
 
+/// The names the session cookie of icourse163 may go by, in order of priority.
+///
+/// `NTES_SESS` is used instead of `NTESSTUDYSI` by some SPOC courses on school.icourse163.org.
+pub const SESSION_COOKIE_NAMES: &[&str] = &["NTESSTUDYSI", "NTES_SESS"];
+
+/// The domains whose cookies are imported, see `--cookie-domain`.
+///
+/// `rookie` filters cookies with a substring match on the host (`LIKE '%domain%'` for Chromium
+/// and Firefox based browsers), which also lets through hosts like `icourse163.org.example.com`,
+/// so the cookies it returns are checked again with [`matches_domain`].
+pub const COOKIE_DOMAINS: &[&str] = &[
+    "icourse163.org",
+    "www.icourse163.org",
+    "school.icourse163.org",
+];
+
+/// Whether a cookie set for `cookie_domain` is one of `domains`, ignoring a leading `.`.
+pub fn matches_domain(cookie_domain: &str, domains: &[impl AsRef<str>]) -> bool {
+    let cookie_domain = cookie_domain.trim_start_matches('.');
+    domains
+        .iter()
+        .any(|domain| cookie_domain.eq_ignore_ascii_case(domain.as_ref()))
+}
+
 #[derive(Debug, Default)]
 pub struct CookieJar(RwLock<CookieStoreImpl>);
 
@@ -19,15 +50,13 @@ impl CookieStore for CookieJar {
             })
             .map(|c| c.into_owned());
 
-        self.0.write().unwrap().store_response_cookies(cookies, url);
+        self.write().store_response_cookies(cookies, url);
     }
 
     fn cookies(&self, url: &Url) -> Option<HeaderValue> {
         let s = self
-            .0
-            .read()
-            .unwrap()
-            .get_request_values(url)
+            .iter_cookies(url)
+            .into_iter()
             .map(|(n, v)| format!("{}={}", n, v))
             .collect::<Vec<String>>()
             .join(";");
@@ -40,7 +69,42 @@ impl CookieStore for CookieJar {
     }
 }
 
+impl From<CookieStoreImpl> for CookieJar {
+    fn from(store: CookieStoreImpl) -> Self {
+        Self::from_store(store)
+    }
+}
+
 impl CookieJar {
+    /// Wrap a [`cookie_store::CookieStore`] filled elsewhere, like after logging in with a
+    /// headless browser.
+    pub fn from_store(store: CookieStoreImpl) -> Self {
+        Self(RwLock::new(store))
+    }
+
+    /// Take the [`cookie_store::CookieStore`] holding the cookies of this jar.
+    pub fn into_store(self) -> CookieStoreImpl {
+        self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // A panic while holding the lock cannot leave the store half updated in a way that matters
+    // for sending cookies, so a poisoned lock is used as is rather than failing every request.
+    fn read(&self) -> RwLockReadGuard<'_, CookieStoreImpl> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, CookieStoreImpl> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The names and values of the cookies which would be sent with a request to `url`.
+    pub fn iter_cookies(&self, url: &Url) -> Vec<(String, String)> {
+        self.read()
+            .get_request_values(url)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
     /// Add cookies to this jar.
     ///
     /// Accepts a string of cookies as a `Cookie` header value.
@@ -48,22 +112,458 @@ impl CookieJar {
         let cookies = cookie::Cookie::split_parse(cookie)
             .filter_map(|c| c.ok())
             .map(|c| c.into_owned());
-        self.0.write().unwrap().store_response_cookies(cookies, url);
+        self.write().store_response_cookies(cookies, url);
     }
 
-    pub fn get_session_id(&self, domain: &Url) -> Option<String> {
-        let cookies = self.cookies(domain).unwrap();
+    /// Add cookies to this jar, as if they were set by a response from `url`.
+    ///
+    /// Unlike [`add_cookie_str`](Self::add_cookie_str), this keeps attributes like the expiry.
+    pub fn add_cookies(&self, cookies: impl IntoIterator<Item = Cookie<'static>>, url: &Url) {
+        self.write()
+            .store_response_cookies(cookies.into_iter(), url);
+    }
+
+    /// Save the unexpired cookies with an expiry in this jar to a JSON file, creating its parent
+    /// directory if needed.
+    ///
+    /// On Unix, only the owner may read the file, as it holds the session.
+    pub fn save_json(&self, path: &Path) -> eyre::Result<()> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path)?;
+        // The mode only applies to new files.
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        let mut writer = BufWriter::new(file);
+        json::save(&self.read(), &mut writer).map_err(eyre::Report::msg)?;
+        Ok(())
+    }
+
+    /// Save the unexpired cookies in this jar to a Netscape `cookies.txt` file, as read by curl
+    /// and wget, and by [`parse_netscape`].
+    pub fn export_netscape(&self, path: &Path) -> eyre::Result<()> {
+        std::fs::write(path, self.to_netscape())?;
+        Ok(())
+    }
 
-        let session_id = Cookie::split_parse(cookies.to_str().unwrap()).find_map(|c| {
-            c.ok().and_then(|c| {
-                if let ("NTESSTUDYSI", value) = c.name_value() {
-                    Some(value.to_string())
-                } else {
-                    None
+    fn to_netscape(&self) -> String {
+        let mut content = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in self.read().iter_unexpired() {
+            let (domain, include_subdomains) = match &cookie.domain {
+                CookieDomain::HostOnly(domain) => (domain.clone(), "FALSE"),
+                CookieDomain::Suffix(domain) => (format!(".{domain}"), "TRUE"),
+                CookieDomain::NotPresent | CookieDomain::Empty => continue,
+            };
+            let http_only = if cookie.http_only() == Some(true) {
+                "#HttpOnly_"
+            } else {
+                ""
+            };
+            let secure = if cookie.secure() == Some(true) {
+                "TRUE"
+            } else {
+                "FALSE"
+            };
+            // Session cookies have an expiry of 0.
+            let expires = match cookie.expires {
+                CookieExpiration::AtUtc(at) => SystemTime::from(at)
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs()),
+                CookieExpiration::SessionEnd => 0,
+            };
+            content.push_str(&format!(
+                "{http_only}{domain}\t{include_subdomains}\t{}\t{secure}\t{expires}\t{}\t{}\n",
+                String::from(&cookie.path),
+                cookie.name(),
+                cookie.value()
+            ));
+        }
+        content
+    }
+
+    /// Load cookies saved by [`save_json`](Self::save_json), skipping expired ones.
+    pub fn load_json(path: &Path) -> eyre::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let store = json::load(reader).map_err(eyre::Report::msg)?;
+        Ok(Self::from_store(store))
+    }
+
+    /// Find the session ID of icourse163 in this jar.
+    ///
+    /// The session cookie is looked up under each of `names` in order, ignoring case, and the
+    /// first one found wins. See [`SESSION_COOKIE_NAMES`] for the usual names.
+    ///
+    /// Fails if there are no cookies for `domain` at all, which usually means the user is not
+    /// logged in.
+    pub fn get_session_id(&self, domain: &Url, names: &[&str]) -> eyre::Result<Option<String>> {
+        let Some(cookies) = self.cookies(domain) else {
+            eyre::bail!(
+                "No cookies found for {} — are you logged in?",
+                domain.host_str().unwrap_or(domain.as_str())
+            );
+        };
+
+        let cookies: Vec<_> = Cookie::split_parse(cookies.to_str()?)
+            .filter_map(|c| c.ok())
+            .collect();
+
+        let session_id = names.iter().find_map(|name| {
+            cookies
+                .iter()
+                .find(|c| c.name().eq_ignore_ascii_case(name))
+                .map(|c| c.value().to_string())
+        });
+
+        Ok(session_id)
+    }
+
+    /// Find when the session cookie expires, looking it up like
+    /// [`get_session_id`](Self::get_session_id).
+    ///
+    /// Returns [`None`] if there is no session cookie, or it has no expiry, which is the case for
+    /// cookies added with [`add_cookie_str`](Self::add_cookie_str).
+    pub fn session_expiry(&self, domain: &Url, names: &[&str]) -> Option<SystemTime> {
+        let store = self.read();
+        let cookie = names.iter().find_map(|name| {
+            store
+                .iter_any()
+                .find(|c| c.matches(domain) && c.name().eq_ignore_ascii_case(name))
+        })?;
+
+        match cookie.expires {
+            CookieExpiration::AtUtc(at) => Some(at.into()),
+            CookieExpiration::SessionEnd => None,
+        }
+    }
+}
+
+/// Find the cookies in a command made by "Copy as cURL" in the developer tools of browsers, as a
+/// `Cookie` header value.
+///
+/// The cookies are taken from `-H 'Cookie: ...'` (or `--header`), or from `-b`/`--cookie` as
+/// newer versions of Chrome do. Both the bash and the Windows `cmd` flavors are understood.
+pub fn cookies_from_curl(command: &str) -> Option<String> {
+    let args = split_command(command);
+    let mut cookies = Vec::new();
+
+    for pair in args.windows(2) {
+        let [option, value] = pair else {
+            continue;
+        };
+        match option.as_str() {
+            "-H" | "--header" => {
+                if let Some((name, value)) = value.split_once(':') {
+                    if name.trim().eq_ignore_ascii_case("cookie") {
+                        cookies.push(value.trim().to_string());
+                    }
                 }
-            })
+            }
+            // A value without `=` names a file to read cookies from, which is not supported.
+            "-b" | "--cookie" if value.contains('=') => cookies.push(value.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    Some(cookies.join("; ")).filter(|cookies| !cookies.is_empty())
+}
+
+/// Split a shell command into its arguments, handling quotes and line continuations.
+///
+/// This understands enough of bash (`'...'`, `"..."`, `$'...'` and `\`) and of `cmd` (`^`
+/// escapes) for the commands made by browsers, not shell syntax in general.
+fn split_command(command: &str) -> Vec<String> {
+    // `cmd` escapes quotes as `^"`, and continues lines with `^`, which turns into quotes as in
+    // bash once `^` is removed.
+    let command = if command.contains("^\"") {
+        let mut unescaped = String::with_capacity(command.len());
+        let mut chars = command.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '^' => unescaped.extend(chars.next().filter(|c| !matches!(c, '\r' | '\n'))),
+                c => unescaped.push(c),
+            }
+        }
+        Cow::Owned(unescaped)
+    } else {
+        Cow::Borrowed(command)
+    };
+
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                // A line continuation.
+                Some('\r' | '\n') => {
+                    chars.next_if_eq(&'\n');
+                }
+                Some(escaped) => current.get_or_insert_default().push(escaped),
+                None => {}
+            },
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let arg = current.get_or_insert_default();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\'' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => arg.push('\n'),
+                            Some('t') => arg.push('\t'),
+                            Some(escaped) => arg.push(escaped),
+                            None => {}
+                        },
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '\'' => {
+                let arg = current.get_or_insert_default();
+                arg.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                let arg = current.get_or_insert_default();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if matches!(chars.peek(), Some('"' | '\\')) => {
+                            arg.extend(chars.next());
+                        }
+                        c => arg.push(c),
+                    }
+                }
+            }
+            c if c.is_whitespace() => args.extend(current.take()),
+            c => current.get_or_insert_default().push(c),
+        }
+    }
+    args.extend(current);
+    args
+}
+
+/// Cookies read from a Netscape `cookies.txt` file.
+#[derive(Debug, Default)]
+pub struct NetscapeCookies {
+    /// The cookies matching the domains, as a `Cookie` header value.
+    pub cookies: String,
+    /// Line numbers (1-based) of the lines which could not be parsed.
+    pub malformed: Vec<usize>,
+}
+
+/// Parse a Netscape `cookies.txt` file, keeping only cookies for `domains`.
+///
+/// Each line holds seven tab-separated fields: domain, include subdomains flag, path, secure,
+/// expiry, name and value. Lines starting with `#` are comments, except for the `#HttpOnly_`
+/// prefix some exporters put before the domain.
+pub fn parse_netscape(content: &str, domains: &[impl AsRef<str>]) -> NetscapeCookies {
+    let mut result = NetscapeCookies::default();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let line = match line.strip_prefix("#HttpOnly_") {
+            Some(line) => line,
+            None if line.trim().is_empty() || line.starts_with('#') => continue,
+            None => line,
+        };
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [cookie_domain, _, _, _, _, name, value] = fields[..] else {
+            result.malformed.push(i + 1);
+            continue;
+        };
+
+        if !matches_domain(cookie_domain, domains) {
+            continue;
+        }
+
+        if !result.cookies.is_empty() {
+            result.cookies.push_str("; ");
+        }
+        result.cookies.push_str(&format!("{name}={value}"));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use cookie::{Cookie, Expiration};
+    use reqwest::Url;
+
+    use super::{
+        cookies_from_curl, matches_domain, parse_netscape, CookieJar, COOKIE_DOMAINS,
+        SESSION_COOKIE_NAMES,
+    };
+
+    #[test]
+    fn session_expiry() {
+        let url = Url::parse("https://www.icourse163.org").unwrap();
+        let expiry = SystemTime::UNIX_EPOCH + Duration::from_secs(4_000_000_000);
+
+        let jar = CookieJar::default();
+        jar.add_cookie_str("NTESSTUDYSI=abc", &url);
+        assert_eq!(jar.session_expiry(&url, SESSION_COOKIE_NAMES), None);
+
+        let mut cookie = Cookie::new("NTESSTUDYSI", "abc");
+        cookie.set_expires(Expiration::DateTime(expiry.into()));
+        jar.add_cookies([cookie], &url);
+        assert_eq!(jar.session_expiry(&url, SESSION_COOKIE_NAMES), Some(expiry));
+    }
+
+    #[test]
+    fn from_store() {
+        let url = Url::parse("https://www.icourse163.org").unwrap();
+        let mut store = cookie_store::CookieStore::default();
+        store
+            .parse("NTESSTUDYSI=abc; Domain=icourse163.org", &url)
+            .unwrap();
+
+        let jar = CookieJar::from_store(store);
+        assert_eq!(
+            jar.iter_cookies(&url),
+            [("NTESSTUDYSI".to_string(), "abc".to_string())]
+        );
+        assert!(jar
+            .iter_cookies(&Url::parse("https://example.com").unwrap())
+            .is_empty());
+        assert_eq!(jar.into_store().iter_any().count(), 1);
+    }
+
+    #[test]
+    fn export() {
+        let url = Url::parse("https://www.icourse163.org").unwrap();
+        let jar = CookieJar::default();
+        jar.add_cookie_str("STUDY_INFO=def", &url);
+        let mut cookie =
+            Cookie::parse("NTESSTUDYSI=abc; Domain=icourse163.org; Secure; HttpOnly").unwrap();
+        cookie.set_expires(Expiration::DateTime(
+            (SystemTime::UNIX_EPOCH + Duration::from_secs(4_000_000_000)).into(),
+        ));
+        jar.add_cookies([cookie], &url);
+
+        let content = jar.to_netscape();
+        let mut lines: Vec<_> = content.lines().skip(1).collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "#HttpOnly_.icourse163.org\tTRUE\t/\tTRUE\t4000000000\tNTESSTUDYSI\tabc",
+                "www.icourse163.org\tFALSE\t/\tFALSE\t0\tSTUDY_INFO\tdef",
+            ]
+        );
+
+        let mut cookies: Vec<_> = parse_netscape(&content, COOKIE_DOMAINS)
+            .cookies
+            .split("; ")
+            .map(String::from)
+            .collect();
+        cookies.sort();
+        assert_eq!(cookies, ["NTESSTUDYSI=abc", "STUDY_INFO=def"]);
+    }
+
+    #[test]
+    fn cache() {
+        let url = Url::parse("https://www.icourse163.org").unwrap();
+        let jar = CookieJar::default();
+        jar.add_cookie_str("STUDY_INFO=def", &url);
+        let mut cookie = Cookie::new("NTESSTUDYSI", "abc");
+        cookie.set_expires(Expiration::DateTime(
+            (SystemTime::now() + Duration::from_secs(3600)).into(),
+        ));
+        jar.add_cookies([cookie], &url);
+
+        let dir = std::env::temp_dir().join(format!("mooc-cookies-{}", std::process::id()));
+        let path = dir.join("cookies.json");
+        jar.save_json(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Cookies ending with the session of the browser are left out.
+        let loaded = CookieJar::load_json(&path).unwrap();
+        assert_eq!(
+            loaded.iter_cookies(&url),
+            [("NTESSTUDYSI".to_string(), "abc".to_string())]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn poisoned() {
+        let url = Url::parse("https://www.icourse163.org").unwrap();
+        let jar = CookieJar::default();
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = jar.0.write().unwrap();
+            panic!("poison the lock");
         });
+        assert!(jar.0.is_poisoned());
+
+        jar.add_cookie_str("NTESSTUDYSI=abc", &url);
+        assert_eq!(
+            jar.get_session_id(&url, SESSION_COOKIE_NAMES).unwrap(),
+            Some("abc".to_string())
+        );
+    }
+
+    #[test]
+    fn curl() {
+        let bash = "curl 'https://www.icourse163.org/learn/XYZ-1234567' \\\n  \
+            -H 'accept: text/html' \\\n  \
+            -H 'cookie: NTESSTUDYSI=abc; STUDY_INFO=def' \\\n  \
+            --compressed";
+        assert_eq!(
+            cookies_from_curl(bash).as_deref(),
+            Some("NTESSTUDYSI=abc; STUDY_INFO=def")
+        );
+
+        let chrome = "curl 'https://www.icourse163.org/' -b $'NTESSTUDYSI=abc; a=\\'b\\''";
+        assert_eq!(
+            cookies_from_curl(chrome).as_deref(),
+            Some("NTESSTUDYSI=abc; a='b'")
+        );
+
+        let cmd = "curl ^\"https://www.icourse163.org/^\" ^\r\n  \
+            --header ^\"Cookie: NTESSTUDYSI=abc^\"";
+        assert_eq!(cookies_from_curl(cmd).as_deref(), Some("NTESSTUDYSI=abc"));
+
+        assert_eq!(cookies_from_curl("curl https://www.icourse163.org/"), None);
+    }
+
+    #[test]
+    fn netscape() {
+        let content = "# Netscape HTTP Cookie File\n\
+            \n\
+            .icourse163.org\tTRUE\t/\tFALSE\t0\tNTESSTUDYSI\tabc\n\
+            #HttpOnly_www.icourse163.org\tFALSE\t/\tTRUE\t0\tSTUDY_INFO\tdef\n\
+            .example.com\tTRUE\t/\tFALSE\t0\tother\tghi\n\
+            mooc.icourse163.org\tFALSE\t/\tFALSE\t0\tother\tjkl\n\
+            broken line\n";
+
+        let cookies = parse_netscape(content, COOKIE_DOMAINS);
+        assert_eq!(cookies.cookies, "NTESSTUDYSI=abc; STUDY_INFO=def");
+        assert_eq!(cookies.malformed, [7]);
+    }
 
-        session_id
+    #[test]
+    fn domains() {
+        assert!(matches_domain(".icourse163.org", COOKIE_DOMAINS));
+        assert!(matches_domain("School.icourse163.org", COOKIE_DOMAINS));
+        assert!(!matches_domain("mooc.icourse163.org", COOKIE_DOMAINS));
+        assert!(!matches_domain(
+            "icourse163.org.example.com",
+            COOKIE_DOMAINS
+        ));
+        assert!(!matches_domain("myicourse163.org", COOKIE_DOMAINS));
     }
 }
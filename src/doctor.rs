@@ -0,0 +1,159 @@
+//! The `doctor` subcommand, checking what usually goes wrong before downloading anything.
+
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use console::style;
+use indicatif::HumanDuration;
+use mooc_pdf_download::{
+    cookies::SESSION_COOKIE_NAMES, get_course_info, get_ids, parse_tid, Session,
+};
+use reqwest::Url;
+
+use crate::{
+    build_client, dwr_params, find_profile, set_cookies, Args, ClientOptions, CookieSource,
+    BROWSERS,
+};
+
+/// Print a line of the checklist.
+fn report(ok: bool, message: impl AsRef<str>) {
+    let mark = if ok {
+        style("✓").green()
+    } else {
+        style("✗").red()
+    };
+    println!("{mark} {}", message.as_ref());
+}
+
+/// Check the cookies of each browser, or of the source given on the command line, then whether
+/// the site can be reached with the first session found.
+///
+/// Fails if no session was found, or the site could not be reached.
+pub async fn run(args: &Args) -> eyre::Result<()> {
+    let domain = Url::parse(&format!("https://{}", args.domain))?;
+    let session_cookie_names = match &args.session_cookie {
+        Some(name) => vec![name.as_str()],
+        None => SESSION_COOKIE_NAMES.to_vec(),
+    };
+
+    let sources = match (&args.browser, &args.cookies_file, &args.cookies) {
+        _ if args.profile.is_some() => vec![CookieSource::Profile(find_profile(
+            args.profile.as_deref().unwrap_or_default(),
+        )?)],
+        (Some(browser), ..) => vec![browser.clone()],
+        (None, Some(path), _) => vec![path.clone().into()],
+        (None, None, Some(cookies)) => vec![CookieSource::Custom(cookies.clone())],
+        (None, None, None) => BROWSERS
+            .iter()
+            .map(|browser| CookieSource::from_str(browser))
+            .collect::<eyre::Result<_>>()?,
+    };
+
+    let mut session = None;
+    for source in sources {
+        let name = match &source {
+            CookieSource::File(path) | CookieSource::Profile(path) => path.display().to_string(),
+            CookieSource::Custom(_) => "--cookies".to_string(),
+            browser => browser.to_string(),
+        };
+        let jar = match set_cookies(source, &domain, &args.cookie_domains) {
+            Ok(jar) => jar,
+            Err(e) => {
+                // Errors of browsers already name them, and tell what to do.
+                report(false, format!("{e:#}"));
+                continue;
+            }
+        };
+        let session_id = match jar.get_session_id(&domain, &session_cookie_names) {
+            Ok(Some(session_id)) => session_id,
+            Ok(None) => {
+                report(
+                    false,
+                    format!(
+                        "{name}: no session cookie ({}), log in to {} first",
+                        session_cookie_names.join(" or "),
+                        args.domain
+                    ),
+                );
+                continue;
+            }
+            Err(e) => {
+                report(false, format!("{name}: {e}"));
+                continue;
+            }
+        };
+
+        match jar.session_expiry(&domain, &session_cookie_names) {
+            Some(expiry) => match expiry.duration_since(SystemTime::now()) {
+                Ok(left) => report(
+                    true,
+                    format!("{name}: session found, expires in {}", HumanDuration(left)),
+                ),
+                Err(_) => {
+                    report(false, format!("{name}: session expired, log in again"));
+                    continue;
+                }
+            },
+            None => report(true, format!("{name}: session found")),
+        }
+        session.get_or_insert((name, jar, session_id));
+    }
+
+    let Some((name, jar, session_id)) = session else {
+        report(
+            false,
+            "No session found, log in to the site in a browser, or pass --cookies",
+        );
+        eyre::bail!("Some checks failed");
+    };
+
+    let client = build_client(&ClientOptions::new(args, Arc::new(jar), &domain))?;
+    let timeout = Duration::from_secs(args.timeout);
+    match client.get(domain.clone()).timeout(timeout).send().await {
+        Ok(response) if response.status().is_success() => {
+            report(true, format!("{domain} reached with the session of {name}"));
+        }
+        Ok(response) => {
+            report(false, format!("{domain} answered {}", response.status()));
+            eyre::bail!("Some checks failed");
+        }
+        Err(e) => {
+            report(false, format!("Failed to reach {domain}: {e:#}"));
+            eyre::bail!("Some checks failed");
+        }
+    }
+
+    // Only a course tells whether the session is accepted, as the site is open to everyone.
+    let Some(tid) = args.tid.first() else {
+        println!("Pass --tid to also check that the units of a course can be read");
+        return Ok(());
+    };
+    let tid = parse_tid(tid)?;
+    let session = Session {
+        site: domain,
+        id: session_id,
+        dwr: dwr_params(args),
+        pacer: None,
+    };
+    match get_course_info(&client, &session, &tid, args.retries + 1, timeout).await {
+        Ok(course_info) => match get_ids(&course_info).len() {
+            0 => {
+                report(
+                    false,
+                    format!("No units found in {tid}, is the session enrolled in it?"),
+                );
+                eyre::bail!("Some checks failed");
+            }
+            count => report(true, format!("{count} unit(s) found in {tid}")),
+        },
+        Err(e) => {
+            report(false, format!("{e:#}"));
+            eyre::bail!("Some checks failed");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,432 @@
+//! Helpers for picking values out of DWR replies.
+//!
+//! A DWR reply is a piece of JavaScript assigning fields to objects named `s0`, `s1`, ..., like
+//! `s12.name="\u7B2C1\u5468";s12.id=1234;`.
+
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use memchr::{memchr, memchr2, memmem::find};
+
+/// Values sent along with every DWR call, which the server may start to validate.
+///
+/// The names of the methods called may be changed as well, in case the site renames them.
+#[derive(Debug, Clone)]
+pub struct DwrParams {
+    /// The `scriptSessionId`. The web page sends a placeholder the server does not check.
+    pub script_session_id: String,
+    /// A fixed `batchId` to send, instead of the current time.
+    pub batch_id: Option<String>,
+    /// The script the methods belong to.
+    pub script_name: String,
+    /// The method fetching the structure of a course, with `{tid}` in its parameters.
+    pub course_info: DwrCall,
+    /// The method fetching the files of a unit, with `{content_id}` and `{section_id}` in its
+    /// parameters.
+    pub unit: DwrCall,
+}
+
+impl Default for DwrParams {
+    fn default() -> Self {
+        Self {
+            script_session_id: "${scriptSessionId}190".to_string(),
+            batch_id: None,
+            script_name: "CourseBean".to_string(),
+            course_info: DwrCall::course_info(),
+            unit: DwrCall::unit(),
+        }
+    }
+}
+
+/// A DWR method and its parameters, written like
+/// `getLessonUnitLearnVo(number:{content_id},number:3,number:0,number:{section_id})`.
+///
+/// Parameters may contain placeholders in braces, filled in for each call by
+/// [`params`](Self::params).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DwrCall {
+    pub method: String,
+    pub params: Vec<String>,
+}
+
+impl DwrCall {
+    /// The call fetching the structure of a course, as made by the web page.
+    pub fn course_info() -> Self {
+        Self {
+            method: "getLastLearnedMocTermDto".to_string(),
+            params: vec!["number:{tid}".to_string()],
+        }
+    }
+
+    /// The call fetching the files of a unit, as made by the web page.
+    pub fn unit() -> Self {
+        Self {
+            method: "getLessonUnitLearnVo".to_string(),
+            params: vec![
+                "number:{content_id}".to_string(),
+                "number:3".to_string(),
+                "number:0".to_string(),
+                "number:{section_id}".to_string(),
+            ],
+        }
+    }
+
+    /// The parameters with each `{name}` replaced by its value in `values`.
+    pub fn params(&self, values: &[(&str, &str)]) -> Vec<String> {
+        self.params
+            .iter()
+            .map(|param| {
+                values.iter().fold(param.clone(), |param, (name, value)| {
+                    param.replace(&format!("{{{name}}}"), value)
+                })
+            })
+            .collect()
+    }
+}
+
+impl Display for DwrCall {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.method, self.params.join(","))
+    }
+}
+
+impl FromStr for DwrCall {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, params) = s
+            .trim()
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| {
+                eyre::eyre!("Expected a call like `method(number:{{tid}})`, got `{s}`")
+            })?;
+        let method = method.trim();
+        if method.is_empty()
+            || !method
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            eyre::bail!("Invalid DWR method name `{method}`");
+        }
+        let params = match params.trim() {
+            "" => Vec::new(),
+            params => params.split(',').map(|p| p.trim().to_string()).collect(),
+        };
+        Ok(Self {
+            method: method.to_string(),
+            params,
+        })
+    }
+}
+
+impl DwrParams {
+    /// The `batchId` of a new call, which is the current time in milliseconds by default.
+    pub fn batch_id(&self) -> String {
+        match &self.batch_id {
+            Some(batch_id) => batch_id.clone(),
+            None => SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+                .to_string(),
+        }
+    }
+}
+
+/// Check whether a DWR reply reports an error instead of data, and describe the error if so.
+///
+/// This happens when the session is invalid, in which case the reply is something like
+/// `throw 'allowScriptTagRemoting is false.';` or a call to `dwr.engine._remoteHandleException`.
+pub fn reply_error(reply: &[u8]) -> Option<String> {
+    const MAX_LEN: usize = 200;
+
+    let pos = find(reply, b"dwr.engine._remoteHandleException")
+        .or_else(|| reply.starts_with(b"throw ").then_some(0))
+        .or_else(|| find(reply, b"\nthrow ").map(|pos| pos + 1));
+
+    match pos {
+        Some(pos) => {
+            let line = &reply[pos..];
+            let line = &line[..memchr(b'\n', line).unwrap_or(line.len()).min(MAX_LEN)];
+            Some(String::from_utf8_lossy(line).trim().to_string())
+        }
+        None if find(reply, b"//#DWR-REPLY").is_none() => Some("not a DWR reply".to_string()),
+        None => None,
+    }
+}
+
+/// Whether a DWR reply carries no data, as it is cut off before the callback handing over the
+/// result, or the result is `null`.
+pub fn reply_is_empty(reply: &[u8]) -> bool {
+    const CALLBACK: &[u8] = b"dwr.engine._remoteHandleCallback(";
+
+    let Some(pos) = find(reply, CALLBACK) else {
+        return true;
+    };
+    let args = &reply[pos + CALLBACK.len()..];
+    let Some(end) = memchr(b')', args) else {
+        return true;
+    };
+    // The result is the last argument, after the batch and call IDs.
+    args[..end]
+        .rsplit(|&b| b == b',')
+        .next()
+        .is_none_or(|result| result.trim_ascii() == b"null")
+}
+
+/// Find the raw value assigned to `field` of the object `ident`, like `1234` or `"name"`.
+///
+/// String values are returned with their quotes, and may be decoded with [`string`].
+pub fn field<'a>(reply: &'a [u8], ident: &str, field: &str) -> Option<&'a [u8]> {
+    let pattern = format!("{ident}.{field}=");
+    let mut start = 0;
+
+    // Make sure `s1.id=` does not match the end of `s11.id=`.
+    let pos = loop {
+        let pos = start + find(&reply[start..], pattern.as_bytes())?;
+        if pos == 0 || !reply[pos - 1].is_ascii_alphanumeric() {
+            break pos + pattern.len();
+        }
+        start = pos + 1;
+    };
+
+    let value = &reply[pos..];
+    let len = if value.first() == Some(&b'"') {
+        string_len(value)?
+    } else {
+        memchr(b';', value).unwrap_or(value.len())
+    };
+
+    Some(&value[..len])
+}
+
+/// The length of the string literal at the start of `value`, including its quotes.
+fn string_len(value: &[u8]) -> Option<usize> {
+    let quote = *value.first()?;
+    let mut pos = 1;
+    loop {
+        pos += memchr2(quote, b'\\', value.get(pos..)?)?;
+        match value[pos] {
+            b'\\' => pos += 2,
+            _ => return Some(pos + 1),
+        }
+    }
+}
+
+/// The fields assigned to each object of a DWR reply.
+///
+/// Unlike [`field`], the reply is read one statement after another, skipping string literals, so
+/// a field is only ever found in the statement assigning it. A statement cut off at the end of
+/// the reply is left out.
+#[derive(Debug, Default)]
+pub struct Objects<'a> {
+    /// The assignments like `s1.id=1234;` as `("s1", "id", b"1234")`, in the order of the reply.
+    assignments: Vec<(&'a str, &'a str, &'a [u8])>,
+    /// Maps each object and field to the last of its `assignments`.
+    index: HashMap<(&'a str, &'a str), usize>,
+}
+
+impl<'a> Objects<'a> {
+    pub fn parse(reply: &'a [u8]) -> Self {
+        let mut objects = Self::default();
+        let mut pos = 0;
+        while pos < reply.len() {
+            match reply[pos] {
+                b if b.is_ascii_whitespace() || b == b';' => pos += 1,
+                // Comments like `//#DWR-REPLY` take the rest of the line.
+                b'/' if reply[pos..].starts_with(b"//") => {
+                    pos += memchr(b'\n', &reply[pos..]).unwrap_or(reply.len() - pos);
+                }
+                _ => {
+                    let Some(len) = statement_len(&reply[pos..]) else {
+                        break;
+                    };
+                    objects.push(&reply[pos..pos + len]);
+                    pos += len + 1;
+                }
+            }
+        }
+        objects
+    }
+
+    /// Record `statement` if it assigns a field of an object, like `s1.id=1234`.
+    fn push(&mut self, statement: &'a [u8]) {
+        let Some(eq) = memchr(b'=', statement) else {
+            return;
+        };
+        let Ok(target) = std::str::from_utf8(&statement[..eq]) else {
+            return;
+        };
+        let Some((ident, field)) = target.trim().split_once('.') else {
+            return;
+        };
+        let is_name = |name: &str| {
+            !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'$')
+        };
+        if !is_name(ident) || !is_name(field) {
+            return;
+        }
+        let value = statement[eq + 1..].trim_ascii();
+        self.index.insert((ident, field), self.assignments.len());
+        self.assignments.push((ident, field, value));
+    }
+
+    /// The raw value assigned to `field` of the object `ident`, like [`field`] returns.
+    pub fn field(&self, ident: &str, field: &str) -> Option<&'a [u8]> {
+        let &index = self.index.get(&(ident, field))?;
+        Some(self.assignments[index].2)
+    }
+
+    /// The objects assigned `field`, along with its values, in the order of the reply.
+    pub fn with_field<'s>(
+        &'s self,
+        field: &'s str,
+    ) -> impl Iterator<Item = (&'a str, &'a [u8])> + 's {
+        self.assignments
+            .iter()
+            .filter(move |(_, name, _)| *name == field)
+            .map(|&(ident, _, value)| (ident, value))
+    }
+}
+
+/// The length of the statement at the start of `reply`, up to its `;`, or `None` if it is not
+/// terminated.
+fn statement_len(reply: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        pos += reply[pos..]
+            .iter()
+            .position(|b| matches!(b, b';' | b'"' | b'\''))?;
+        match reply[pos] {
+            b';' => return Some(pos),
+            _ => pos += string_len(&reply[pos..])?,
+        }
+    }
+}
+
+/// Decode a JavaScript string literal, like `"\u7B2C1\u5468"`.
+///
+/// Returns [`None`] if `raw` is not a string literal, for example `null`.
+pub fn string(raw: &[u8]) -> Option<String> {
+    let raw = std::str::from_utf8(raw.strip_prefix(b"\"")?.strip_suffix(b"\"")?).ok()?;
+
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    let mut pending_surrogate = None;
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'u' => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let unit = u16::from_str_radix(&hex, 16).ok()?;
+                match pending_surrogate.take() {
+                    Some(high) => result.extend(char::decode_utf16([high, unit]).flatten()),
+                    None if (0xD800..0xDC00).contains(&unit) => pending_surrogate = Some(unit),
+                    None => result.extend(char::decode_utf16([unit]).flatten()),
+                }
+            }
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            't' => result.push('\t'),
+            c => result.push(c),
+        }
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field, reply_error, reply_is_empty, string, DwrCall, Objects};
+
+    #[test]
+    fn calls() {
+        let unit = DwrCall::unit();
+        assert_eq!(unit.to_string().parse::<DwrCall>().unwrap(), unit);
+        assert_eq!(
+            unit.params(&[("content_id", "1"), ("section_id", "2")]),
+            ["number:1", "number:3", "number:0", "number:2"]
+        );
+
+        let call: DwrCall = " getTermDto( number:{tid} ,string:x) ".parse().unwrap();
+        assert_eq!(call.method, "getTermDto");
+        assert_eq!(
+            call.params(&[("tid", "1470000000")]),
+            ["number:1470000000", "string:x"]
+        );
+        assert!("noParams()".parse::<DwrCall>().unwrap().params.is_empty());
+        assert!("getTermDto".parse::<DwrCall>().is_err());
+        assert!("CourseBean.getTermDto()".parse::<DwrCall>().is_err());
+    }
+
+    #[test]
+    fn fields() {
+        let reply = br#"s11.id=2;s1.id=1;s1.name="a;\"b\"";s1.contentId=null;"#;
+        assert_eq!(field(reply, "s1", "id"), Some(&b"1"[..]));
+        assert_eq!(field(reply, "s11", "id"), Some(&b"2"[..]));
+        assert_eq!(field(reply, "s1", "name"), Some(&br#""a;\"b\"""#[..]));
+        assert_eq!(field(reply, "s2", "id"), None);
+    }
+
+    #[test]
+    fn objects() {
+        let reply = br#"//#DWR-REPLY
+var s0={};var s1={};
+s1.name="s0.id=9;";s1.id=2;s0.contentId=3;
+s0.id=1;dwr.engine._remoteHandleCallback('1','0',{a:';'});
+s0.name="cut"#;
+        let objects = Objects::parse(reply);
+        assert_eq!(objects.field("s0", "id"), Some(&b"1"[..]));
+        assert_eq!(objects.field("s1", "id"), Some(&b"2"[..]));
+        assert_eq!(objects.field("s1", "name"), Some(&br#""s0.id=9;""#[..]));
+        assert_eq!(objects.field("s0", "name"), None);
+        assert_eq!(
+            objects.with_field("id").collect::<Vec<_>>(),
+            [("s1", &b"2"[..]), ("s0", &b"1"[..])]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(reply_error(b"//#DWR-REPLY\ns0.id=1;"), None);
+        assert_eq!(
+            reply_error(b"throw 'allowScriptTagRemoting is false.';").as_deref(),
+            Some("throw 'allowScriptTagRemoting is false.';")
+        );
+        assert!(reply_error(
+            b"//#DWR-REPLY\ndwr.engine._remoteHandleException('0','0',{message:\"x\"});"
+        )
+        .is_some());
+        assert!(reply_error(b"<html></html>").is_some());
+
+        let reply =
+            b"//#DWR-REPLY\nvar s0={};s0.id=1;\ndwr.engine._remoteHandleCallback('1','0',s0);";
+        assert!(!reply_is_empty(reply));
+        assert!(reply_is_empty(
+            b"//#DWR-REPLY\ndwr.engine._remoteHandleCallback('1','0',null);"
+        ));
+        assert!(reply_is_empty(&reply[..30]));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            string(br#""\u7B2C\u4E00\u5468 \"1\"""#).as_deref(),
+            Some("第一周 \"1\"")
+        );
+        assert_eq!(string(br#""\uD83D\uDE00""#).as_deref(), Some("😀"));
+        assert_eq!(string(b"null"), None);
+    }
+}
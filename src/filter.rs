@@ -0,0 +1,70 @@
+//! Choosing which files to download by their names, see [`FileFilter`].
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// Glob patterns which the names of the files to download must match, like `*week3*`.
+///
+/// Files matching any of the excluded patterns are left out, even if they match an included one.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    /// `None` without any pattern, which includes every file.
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl FileFilter {
+    pub fn new(
+        include: &[impl AsRef<str>],
+        exclude: &[impl AsRef<str>],
+    ) -> Result<Self, globset::Error> {
+        Ok(Self {
+            include: glob_set(include)?,
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    /// Whether the file named `file_name` should be downloaded.
+    pub fn matches(&self, file_name: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(file_name))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|exclude| exclude.is_match(file_name))
+    }
+}
+
+fn glob_set(patterns: &[impl AsRef<str>]) -> Result<Option<GlobSet>, globset::Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern.as_ref())?);
+    }
+    builder.build().map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileFilter;
+
+    #[test]
+    fn filters() {
+        let all = FileFilter::default();
+        assert!(all.matches("notes.pdf"));
+
+        let filter = FileFilter::new(&["*week3*", "*.pptx"], &["*answer*"]).unwrap();
+        assert!(filter.matches("week3-notes.pdf"));
+        assert!(filter.matches("slides.pptx"));
+        assert!(!filter.matches("week2-notes.pdf"));
+        assert!(!filter.matches("week3-answers.pdf"));
+
+        let filter = FileFilter::new(&[] as &[&str], &["*answer*"]).unwrap();
+        assert!(filter.matches("week2-notes.pdf"));
+        assert!(!filter.matches("answers.pdf"));
+
+        assert!(FileFilter::new(&["[week"], &[] as &[&str]).is_err());
+    }
+}
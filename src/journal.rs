@@ -0,0 +1,77 @@
+//! A record of the courses fully downloaded into an output directory, so that an interrupted
+//! batch can continue where it stopped, and later runs only download the units added since.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::ErrorKind,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{read, rename, write};
+
+/// The courses fully downloaded into an output directory, saved as `journal.json` in it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    /// The tids of the courses with all their files downloaded.
+    pub completed: BTreeSet<String>,
+    /// The content IDs of the units with all their files downloaded, by the tid of their course.
+    #[serde(default)]
+    pub units: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Journal {
+    /// The name of the journal in the output directory.
+    pub const FILE_NAME: &str = "journal.json";
+
+    /// Load the journal at `path`, or an empty one if there is none yet.
+    pub async fn load(path: &Path) -> eyre::Result<Self> {
+        match read(path).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the journal to `path`, replacing the previous one only once fully written.
+    pub async fn save(&self, path: &Path) -> eyre::Result<()> {
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        write(&part_path, serde_json::to_vec_pretty(self)?).await?;
+        rename(&part_path, path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Journal;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("mooc-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(Journal::FILE_NAME);
+
+        let mut journal = Journal::load(&path).await.unwrap();
+        assert!(journal.completed.is_empty());
+
+        journal.completed.insert("1470000000".to_string());
+        journal
+            .units
+            .entry("1470000000".to_string())
+            .or_default()
+            .insert("1234".to_string());
+        journal.save(&path).await.unwrap();
+        let journal = Journal::load(&path).await.unwrap();
+        assert!(journal.completed.contains("1470000000"));
+        assert!(journal.units["1470000000"].contains("1234"));
+
+        // Journals written before units were recorded are still read.
+        std::fs::write(&path, r#"{"completed":["1470000000"]}"#).unwrap();
+        let journal = Journal::load(&path).await.unwrap();
+        assert!(journal.units.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
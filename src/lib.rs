@@ -0,0 +1,2632 @@
+//! Download the PDF files of courses on [icourse163](https://www.icourse163.org).
+//!
+//! The pipeline goes from [`get_course_info`] to [`get_ids`], [`get_pdf_urls`] and finally
+//! [`download`], with a [`Client`] made by [`client_builder`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::AsRef,
+    fmt::{Display, Formatter},
+    future::pending,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use clap::ValueEnum;
+use console::{Color, Style};
+use encoding_rs::{Encoding, UTF_8};
+use eyre::{OptionExt as _, WrapErr as _};
+use indexmap::{indexmap, IndexMap};
+use indicatif::{
+    BinaryBytes, HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle,
+};
+use memchr::memchr;
+use regex::bytes::Regex;
+use reqwest::{
+    header::{
+        HeaderMap, HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_DISPOSITION,
+        CONTENT_LENGTH, CONTENT_TYPE, RANGE,
+    },
+    Client, ClientBuilder, Response, StatusCode, Url,
+};
+use serde::Serialize;
+use sha2::{Digest as _, Sha256};
+use tokio::{
+    fs::{create_dir_all, metadata, remove_file, rename, File, OpenOptions},
+    io::{AsyncReadExt as _, AsyncWriteExt as _, BufWriter},
+    select,
+    sync::{mpsc, watch},
+    task::{spawn_blocking, JoinSet},
+    time::sleep,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    archive::Archive,
+    cookies::CookieJar,
+    dwr::{DwrCall, DwrParams},
+    filter::FileFilter,
+    manifest::{
+        display_relative, file_digest, hash_file, hex_digest, FileStatus, Manifest, ManifestEntry,
+    },
+    progress::{Event, FileProgress, ProgressMode},
+    query_string::{parse_query_string, unquote_lossy, unquote_plus_lossy, unquote_to_bytes},
+    retry::{backoff, retry_after, with_retry},
+    template::{OutputTemplate, Placeholder},
+    throttle::{Concurrency, Pacer, Pause, Quota, QuotaReached, RateLimiter},
+};
+
+pub mod archive;
+pub mod cookies;
+pub mod dwr;
+pub mod filter;
+pub mod journal;
+pub mod manifest;
+pub mod merge;
+pub mod progress;
+pub mod query_string;
+pub mod retry;
+pub mod template;
+pub mod throttle;
+pub mod url_cache;
+pub mod user_agents;
+
+/// A [`ClientBuilder`] sending the cookies in `cookie_jar`, with `user_agent` as the User-Agent.
+///
+/// Further options like timeouts and proxies may be set on the returned builder.
+pub fn client_builder(cookie_jar: Arc<CookieJar>, user_agent: &str) -> ClientBuilder {
+    Client::builder()
+        .cookie_provider(cookie_jar)
+        .user_agent(user_agent)
+}
+
+/// Options controlling how files are downloaded.
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Bounds the number of files downloaded at the same time, in total and from each host, and
+    /// may be shared with other downloads to bound them all together.
+    pub concurrency: Arc<Concurrency>,
+    /// The number of attempts for each request.
+    pub attempts: u32,
+    /// The number of times to download a file again after it failed, on top of the retries of
+    /// each request.
+    pub file_retries: u32,
+    /// Download files again even if they already exist.
+    pub force: bool,
+    /// What to do with files which already exist, unless [`Self::force`] is set.
+    pub on_exist: OnExist,
+    /// How to tell apart files of the course which would be saved at the same path.
+    pub on_duplicate: OnDuplicate,
+    /// Put the position of each file in the course before its name, like `007-notes.pdf`.
+    pub number: bool,
+    /// Put all files directly in the output directory.
+    pub flat: bool,
+    /// Skips files whose names do not match, before downloading anything.
+    pub filter: FileFilter,
+    /// Skips files smaller than this many bytes, if the server tells their size.
+    pub min_size: Option<u64>,
+    /// Skips files larger than this many bytes, if the server tells their size.
+    pub max_size: Option<u64>,
+    /// How the progress of the downloads is shown.
+    pub progress: ProgressMode,
+    /// Fits the names of files to this many columns in progress bars, so that the bars line up.
+    pub name_width: Option<usize>,
+    /// Gives up on a file when no data is received for this long, to be retried like other
+    /// failures.
+    pub idle_timeout: Option<Duration>,
+    /// Limits the total throughput of the downloads.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Caps the total size of the downloads, skipping the files which do not fit.
+    pub quota: Option<Arc<Quota>>,
+    /// Pauses all the downloads when the server answers with HTTP 429 Too Many Requests.
+    pub pause: Arc<Pause>,
+    /// Where to put each file in the output directory, instead of the default layout.
+    pub template: Option<OutputTemplate>,
+    /// Stops the downloads when set to `true`, removing their partial files. Partial files of
+    /// downloads which failed otherwise are kept if they can be resumed.
+    pub cancel: Option<watch::Receiver<bool>>,
+    /// Also bundle the downloaded files and the manifest into a zip archive at this path.
+    pub zip: Option<PathBuf>,
+}
+
+/// What to do when a file to download already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnExist {
+    /// Download the file again if its size differs from the remote one.
+    Overwrite,
+    /// Keep the existing file.
+    #[default]
+    Skip,
+    /// Save the file under a new name like `notes (1).pdf`, unless a file with the same size
+    /// exists already.
+    Rename,
+}
+
+/// How to rename files of a course which would be saved at the same path, like two `notes.pdf`
+/// with `--flat`.
+///
+/// The names only depend on the course, so they stay the same across runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnDuplicate {
+    /// Put the number of the chapter before the file name, like `02 - notes.pdf`.
+    #[default]
+    Chapter,
+    /// Put the name of the section before the file name, like `Lesson 1 - notes.pdf`.
+    Section,
+    /// Stop before downloading anything.
+    Error,
+}
+
+impl DownloadOptions {
+    /// Whether a file of `size` bytes is within [`Self::min_size`] and [`Self::max_size`].
+    fn size_allowed(&self, size: u64) -> bool {
+        self.min_size.is_none_or(|min| size >= min) && self.max_size.is_none_or(|max| size <= max)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|cancel| *cancel.borrow())
+    }
+
+    /// Wait until the downloads are cancelled, which never happens without [`Self::cancel`].
+    async fn cancelled(&self) {
+        if let Some(cancel) = &self.cancel {
+            if cancel
+                .clone()
+                .wait_for(|&cancelled| cancelled)
+                .await
+                .is_ok()
+            {
+                return;
+            }
+        }
+        pending().await
+    }
+}
+
+/// A login session on an icourse163 site, which the DWR API is called with.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// The site, like `https://www.icourse163.org`, or `https://school.icourse163.org` for SPOC
+    /// courses.
+    pub site: Url,
+    /// The value of the session cookie, or empty to use the site without logging in.
+    pub id: String,
+    /// The other values sent with DWR calls.
+    pub dwr: DwrParams,
+    /// Spaces out DWR calls, regardless of how many are allowed at the same time.
+    pub pacer: Option<Arc<Pacer>>,
+}
+
+impl Session {
+    /// Wait for the turn of a DWR call, see [`Self::pacer`].
+    async fn pace(&self) {
+        if let Some(pacer) = &self.pacer {
+            pacer.wait().await;
+        }
+    }
+
+    /// The URL of the DWR method `{script}.{method}` on the site, see [`DwrParams::script_name`].
+    fn dwr_url(&self, method: &str) -> eyre::Result<Url> {
+        let script = &self.dwr.script_name;
+        Ok(self
+            .site
+            .join(&format!("/dwr/call/plaincall/{script}.{method}.dwr"))?)
+    }
+
+    /// The form of a DWR call to `call`, with the placeholders in its parameters filled from
+    /// `values`.
+    fn dwr_form(&self, call: &DwrCall, values: &[(&str, &str)]) -> IndexMap<String, String> {
+        let mut form = indexmap! {
+            "callCount".to_string() => "1".to_string(),
+            "scriptSessionId".to_string() => self.dwr.script_session_id.clone(),
+            "httpSessionId".to_string() => self.id.clone(),
+            "c0-scriptName".to_string() => self.dwr.script_name.clone(),
+            "c0-methodName".to_string() => call.method.clone(),
+            "c0-id".to_string() => "0".to_string(),
+        };
+        for (i, param) in call.params(values).into_iter().enumerate() {
+            form.insert(format!("c0-param{i}"), param);
+        }
+        form.insert("batchId".to_string(), self.dwr.batch_id());
+        form
+    }
+}
+
+fn headers() -> HeaderMap {
+    let mut header = HeaderMap::new();
+    header.insert("content-type", HeaderValue::from_static("text/plain"));
+    header
+}
+
+/// Get the tid of a course from either the tid itself or the URL of a course page, like
+/// `https://www.icourse163.org/learn/XYZ-1234567?tid=1470000000#/learn/announce`.
+pub fn parse_tid(input: &str) -> eyre::Result<String> {
+    let input = input.trim();
+    if !input.is_empty() && input.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(input.to_string());
+    }
+
+    let url = Url::parse(input).wrap_err_with(|| format!("Not a tid or a course URL: {input}"))?;
+    let tid = url
+        .query()
+        .into_iter()
+        .chain(
+            url.fragment()
+                .and_then(|fragment| fragment.split_once('?'))
+                .map(|(_, q)| q),
+        )
+        .flat_map(parse_query_string)
+        .find_map(|(key, value)| (key == "tid").then_some(value))
+        .filter(|tid| !tid.is_empty() && tid.bytes().all(|b| b.is_ascii_digit()));
+
+    tid.ok_or_else(|| {
+        eyre::eyre!(
+            "No tid found in {input}. Open the course from your course list, the URL should look \
+             like https://www.icourse163.org/learn/XYZ-1234567?tid=1470000000"
+        )
+    })
+}
+
+/// The error of [`get_course_info`] when the site answers with a DWR error instead of the course.
+#[derive(Debug, Clone)]
+pub struct Rejected {
+    /// The error in the reply, see [`dwr::reply_error`].
+    pub reply: String,
+    /// Whether the request was sent with a session.
+    pub logged_in: bool,
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.logged_in {
+            write!(
+                f,
+                "icourse163 rejected the request, your session has likely expired. Please log in \
+                 again, or pass --refresh-cookies if the cookies are cached.\n\nReply: {}",
+                self.reply
+            )
+        } else {
+            write!(
+                f,
+                "icourse163 rejected the request, the course likely requires logging in. Please \
+                 provide cookies instead of --no-auth.\n\nReply: {}",
+                self.reply
+            )
+        }
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Fetch the structure of the course `tid`, as a raw DWR reply.
+///
+/// Each request is tried up to `attempts` times. Fails with [`Rejected`] if the reply is a DWR
+/// error.
+pub async fn get_course_info(
+    client: &Client,
+    session: &Session,
+    tid: &str,
+    attempts: u32,
+    timeout: Duration,
+) -> eyre::Result<Bytes> {
+    let call = &session.dwr.course_info;
+    let form = session.dwr_form(call, &[("tid", tid)]);
+
+    let url = &session.dwr_url(&call.method)?;
+    debug!(%url, tid, "Fetching course info");
+
+    let form = &form;
+    session.pace().await;
+    let bytes = with_retry(attempts, || async move {
+        client
+            .post(url.clone())
+            .headers(headers())
+            .form(form)
+            .timeout(timeout)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+    })
+    .await
+    .wrap_err_with(|| format!("Failed to fetch the course info of {tid}"))?;
+
+    if let Some(reply) = dwr::reply_error(&bytes) {
+        return Err(Rejected {
+            reply,
+            logged_in: !session.id.is_empty(),
+        }
+        .into());
+    }
+
+    Ok(bytes)
+}
+
+/// A unit of the course, which may have files attached.
+#[derive(Debug, Clone, Serialize)]
+pub struct Unit {
+    pub content_id: String,
+    pub section_id: String,
+    /// The name of the chapter (usually a week) containing this unit.
+    pub chapter_name: Option<String>,
+    /// The name of the section (lesson) containing this unit.
+    pub section_name: Option<String>,
+}
+
+/// Find the units of a course in the reply of [`get_course_info`].
+///
+/// The IDs of a unit are read from the same DWR object, wherever the objects are assigned in the
+/// reply.
+pub fn get_ids(course_info: &Bytes) -> Vec<Unit> {
+    let is_number = |raw: &[u8]| !raw.is_empty() && raw.iter().all(u8::is_ascii_digit);
+    let objects = dwr::Objects::parse(course_info);
+
+    // Maps the IDs of chapters and sections to the DWR objects holding them.
+    let mut holders = HashMap::new();
+    for (ident, id) in objects.with_field("id").filter(|(_, id)| is_number(id)) {
+        holders.entry(id).or_insert(ident);
+    }
+
+    let name_of = |ident: &str, id_field: &str| {
+        let id = objects.field(ident, id_field)?;
+        objects
+            .field(holders.get(id)?, "name")
+            .and_then(dwr::string)
+    };
+
+    objects
+        .with_field("contentId")
+        .filter(|(_, content_id)| is_number(content_id))
+        .filter_map(|(ident, content_id)| {
+            let content_id = String::from_utf8_lossy(content_id);
+
+            let Some(section_id) = objects.field(ident, "id").filter(|id| is_number(id)) else {
+                warn!(content_id = %content_id, "No section ID found, skipped");
+                return None;
+            };
+
+            let section_id = String::from_utf8_lossy(section_id);
+            debug!(content_id = %content_id, section_id = %section_id, "Found unit");
+
+            Some(Unit {
+                content_id: content_id.into_owned(),
+                section_id: section_id.into_owned(),
+                chapter_name: name_of(ident, "chapterId"),
+                section_name: name_of(ident, "lessonId"),
+            })
+        })
+        .collect()
+}
+
+/// The name of the course, like `高等数学（一）`.
+pub fn get_course_name(course_info: &Bytes) -> Option<String> {
+    static REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(s[0-9]+)\.courseName=").unwrap());
+
+    REGEX.captures_iter(course_info).find_map(|cap| {
+        let (_, [ident]) = cap.extract();
+        let ident = String::from_utf8_lossy(ident);
+        dwr::field(course_info, &ident, "courseName")
+            .and_then(dwr::string)
+            .filter(|name| !name.trim().is_empty())
+    })
+}
+
+/// The course a term belongs to, and the terms of that course.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Terms {
+    /// The ID of the course, shared by all its terms.
+    pub course_id: Option<String>,
+    /// The tids of the terms referenced in the reply, including the requested one, oldest first.
+    pub tids: Vec<String>,
+}
+
+/// Find the course and the terms referenced in the reply of [`get_course_info`].
+pub fn get_terms(course_info: &Bytes) -> Terms {
+    static REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(s[0-9]+)\.courseName=").unwrap());
+    static TERM_REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"\.termId=([0-9]+);").unwrap());
+
+    let number = |raw: &[u8]| {
+        Some(raw)
+            .filter(|raw| !raw.is_empty() && raw.iter().all(u8::is_ascii_digit))
+            .map(|raw| String::from_utf8_lossy(raw).into_owned())
+    };
+
+    let mut terms = Terms::default();
+    // The term itself is the object holding the name of the course.
+    if let Some(cap) = REGEX.captures(course_info) {
+        let ident = String::from_utf8_lossy(&cap[1]);
+        terms.course_id = dwr::field(course_info, &ident, "courseId").and_then(number);
+        terms
+            .tids
+            .extend(dwr::field(course_info, &ident, "id").and_then(number));
+    }
+    terms.tids.extend(
+        TERM_REGEX
+            .captures_iter(course_info)
+            .filter_map(|cap| number(&cap[1])),
+    );
+
+    terms
+        .tids
+        .sort_by_key(|tid| tid.parse::<u64>().unwrap_or_default());
+    terms.tids.dedup();
+    terms
+}
+
+/// Someone teaching a course.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Instructor {
+    pub name: String,
+    /// Like `教授`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// What the reply of [`get_course_info`] tells about a term, saved next to its files to keep
+/// track of where they come from once the course is taken down.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CourseMetadata {
+    pub tid: Option<String>,
+    pub course_id: Option<String>,
+    pub course_name: Option<String>,
+    pub school: Option<String>,
+    pub instructors: Vec<Instructor>,
+    /// When the term starts, in milliseconds since the Unix epoch.
+    pub start_time: Option<u64>,
+    /// When the term ends, in milliseconds since the Unix epoch.
+    pub end_time: Option<u64>,
+}
+
+impl CourseMetadata {
+    /// The name of the file saved in the folder of a course.
+    pub const FILE_NAME: &str = "course.json";
+
+    pub async fn save(&self, path: &Path) -> eyre::Result<()> {
+        tokio::fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+}
+
+/// Find what the reply of [`get_course_info`] tells about the term, see [`CourseMetadata`].
+///
+/// The school is read from the panel of the term, or else from any `schoolName`, and the
+/// instructors from the objects with a `realName`, in the order of the reply.
+pub fn get_course_metadata(course_info: &Bytes) -> CourseMetadata {
+    let objects = dwr::Objects::parse(course_info);
+    let text = |ident: &str, field: &str| {
+        objects
+            .field(ident, field)
+            .and_then(dwr::string)
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+    let number = |ident: &str, field: &str| {
+        objects
+            .field(ident, field)
+            .filter(|raw| !raw.is_empty() && raw.iter().all(u8::is_ascii_digit))
+            .map(|raw| String::from_utf8_lossy(raw).into_owned())
+    };
+
+    let mut metadata = CourseMetadata::default();
+    // The term itself is the object holding the name of the course.
+    if let Some((term, _)) = objects
+        .with_field("courseName")
+        .find(|(_, raw)| dwr::string(raw).is_some())
+    {
+        metadata.tid = number(term, "id");
+        metadata.course_id = number(term, "courseId");
+        metadata.course_name = text(term, "courseName");
+        metadata.start_time = number(term, "startTime").and_then(|t| t.parse().ok());
+        metadata.end_time = number(term, "endTime").and_then(|t| t.parse().ok());
+        metadata.school = objects
+            .field(term, "schoolPanel")
+            .and_then(|panel| std::str::from_utf8(panel).ok())
+            .and_then(|panel| text(panel, "name"));
+    }
+    if metadata.school.is_none() {
+        metadata.school = objects
+            .with_field("schoolName")
+            .find_map(|(ident, _)| text(ident, "schoolName"));
+    }
+
+    for (ident, _) in objects.with_field("realName") {
+        let Some(name) = text(ident, "realName") else {
+            continue;
+        };
+        if metadata.instructors.iter().all(|i| i.name != name) {
+            metadata.instructors.push(Instructor {
+                name,
+                title: text(ident, "lectorTitle"),
+            });
+        }
+    }
+    metadata
+}
+
+/// A file to download, along with the unit it is attached to.
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub url: Url,
+    /// The content ID of the unit, if known, see [`Unit::content_id`].
+    pub content_id: Option<String>,
+    pub chapter_name: Option<String>,
+    pub section_name: Option<String>,
+}
+
+/// The files attached to units, found by [`get_pdf_urls`].
+#[derive(Debug, Default)]
+pub struct FoundFiles {
+    pub resources: Vec<Resource>,
+    /// The content IDs of the units whose files could not be found, with the reason.
+    ///
+    /// The other units are still worth downloading, so these do not fail [`get_pdf_urls`].
+    pub failed: Vec<(String, eyre::Report)>,
+}
+
+/// Find the files attached to `units`, keeping the order of the units.
+///
+/// Only files with one of `extensions` (without the dot, compared case-insensitively) are kept,
+/// and `*` matches any extension. Units without such a file are left out.
+///
+/// Each request holds a permit of `concurrency` while in flight.
+pub async fn get_pdf_urls(
+    client: &Client,
+    session: &Session,
+    units: &[Unit],
+    extensions: &[String],
+    concurrency: &Arc<Concurrency>,
+    attempts: u32,
+    timeout: Duration,
+) -> eyre::Result<FoundFiles> {
+    let call = &session.dwr.unit;
+    let dwr_url = session.dwr_url(&call.method)?;
+
+    let mut join_set = JoinSet::new();
+    let (tx, mut rx) = mpsc::channel(5);
+    for (index, unit) in units.iter().enumerate() {
+        let form = session.dwr_form(
+            call,
+            &[
+                ("content_id", &unit.content_id),
+                ("section_id", &unit.section_id),
+            ],
+        );
+
+        let client = client.clone();
+        let tx = tx.clone();
+        let concurrency = concurrency.clone();
+
+        let request = client.post(dwr_url.clone()).form(&form).timeout(timeout);
+        let content_id = unit.content_id.clone();
+        let dwr_url = dwr_url.clone();
+        let session = session.clone();
+
+        join_set.spawn(async move {
+            let result = async {
+                let _permit = concurrency.acquire(&dwr_url).await?;
+                session.pace().await;
+                debug!(url = %dwr_url, content_id, "Fetching file URL");
+                let request = &request;
+                let s = with_retry(attempts, || async move {
+                    request
+                        .try_clone()
+                        .expect("form requests can be cloned")
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .bytes()
+                        .await
+                })
+                .await?;
+                if let Some(error) = dwr::reply_error(&s) {
+                    eyre::bail!("icourse163 rejected the request: {error}");
+                }
+
+                // A unit may have several files attached, like slides and a reading.
+                for (position, url) in file_urls(&s).into_iter().enumerate() {
+                    debug!(content_id, url, "Found file URL");
+                    tx.send((index, position, url)).await?;
+                }
+                eyre::Ok(())
+            }
+            .await;
+            result.map_err(|e| {
+                debug!(
+                    content_id,
+                    "Failed to fetch the file URLs of the unit: {e:#}"
+                );
+                (content_id, e)
+            })
+        });
+    }
+
+    // There is still one instance of `tx`, and we need to drop it to close the channel.
+    drop(tx);
+
+    let mut urls = Vec::new();
+
+    while let Some((index, position, url)) = rx.recv().await {
+        let url = Url::parse(&url).wrap_err_with(|| format!("Invalid file URL {url}"))?;
+        if has_extension(&url, extensions) {
+            urls.push((index, position, url));
+        } else {
+            debug!(%url, "Skipped file with another extension");
+        }
+    }
+
+    // Keep the order of the course, regardless of which request finished first.
+    urls.sort_by_key(|(index, position, _)| (*index, *position));
+
+    // The same file may be attached to several units, only the first one is kept.
+    let mut seen = HashSet::new();
+    urls.retain(|(_, _, url)| seen.insert(url.clone()));
+
+    let resources = urls
+        .into_iter()
+        .map(|(index, _, url)| Resource {
+            url,
+            content_id: Some(units[index].content_id.clone()),
+            chapter_name: units[index].chapter_name.clone(),
+            section_name: units[index].section_name.clone(),
+        })
+        .collect();
+
+    // All the tasks are done, as they dropped their senders.
+    let mut failed = Vec::new();
+    while let Some(result) = join_set.join_next().await {
+        if let Err(failure) = result? {
+            failed.push(failure);
+        }
+    }
+    if let Some((content_id, e)) = failed.first() {
+        warn!(
+            "{} of {} unit(s) failed to resolve their file URLs, their files are left out. The \
+             first one, {content_id}: {e:#}",
+            failed.len(),
+            units.len()
+        );
+    }
+
+    Ok(FoundFiles { resources, failed })
+}
+
+/// The URLs of the files in a reply of `getLessonUnitLearnVo`, in order and without duplicates.
+pub fn file_urls(reply: &[u8]) -> Vec<String> {
+    static REGEX: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r#"textOrigUrl:"([^"]+)""#).unwrap());
+
+    let mut urls = Vec::new();
+    for cap in REGEX.captures_iter(reply) {
+        let url = String::from_utf8_lossy(&cap[1]).into_owned();
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+/// What [`download_file`] did with a file, when it did not fail.
+enum Fetched {
+    /// Downloaded to the path, with its size and digest.
+    Downloaded(PathBuf, (u64, String)),
+    /// Kept as it already existed at the path, with what to record for it, see [`kept_status`].
+    Existing(PathBuf, FileStatus),
+    /// Not downloaded, as its size is out of the allowed range.
+    OutOfRange,
+}
+
+async fn download_file(
+    client: &Client,
+    url: Url,
+    path: &Path,
+    file_name: &str,
+    options: &DownloadOptions,
+    previous: &HashMap<PathBuf, FileStatus>,
+    multi_progress: &MultiProgress,
+) -> eyre::Result<Fetched> {
+    // Compressed responses are decoded by the client, so sizes and ranges are asked for without
+    // compression to match the files on disk.
+    let content_length = || async {
+        let response = with_retry(options.attempts, || async {
+            client
+                .head(url.clone())
+                .header(ACCEPT_ENCODING, "identity")
+                .send()
+                .await?
+                .error_for_status()
+        })
+        .await?;
+        eyre::Ok(head_content_length(&response))
+    };
+
+    let mut path = path.to_path_buf();
+    if !options.force {
+        match options.on_exist {
+            OnExist::Overwrite => {
+                if let Ok(existing) = metadata(&path).await {
+                    if content_length().await? == Some(existing.len()) {
+                        info!(file = file_name, "Already exists, skipped");
+                        let status = kept_status(&path, previous).await?;
+                        return Ok(Fetched::Existing(path, status));
+                    }
+                }
+            }
+            OnExist::Skip => {
+                if metadata(&path).await.is_ok() {
+                    info!(file = file_name, "Already exists, skipped");
+                    let status = kept_status(&path, previous).await?;
+                    return Ok(Fetched::Existing(path, status));
+                }
+            }
+            OnExist::Rename => {
+                let mut remote_len = None;
+                for n in 1.. {
+                    let Ok(existing) = metadata(&path).await else {
+                        break;
+                    };
+                    if remote_len.is_none() {
+                        remote_len = Some(content_length().await?);
+                    }
+                    // The same file may have been saved under a new name by an earlier run.
+                    if remote_len == Some(Some(existing.len())) {
+                        info!(file = %path.display(), "Already exists, skipped");
+                        let status = kept_status(&path, previous).await?;
+                        return Ok(Fetched::Existing(path, status));
+                    }
+                    path = numbered_path(&path, n);
+                }
+            }
+        }
+    }
+    let path = &path;
+
+    let part_path = part_path(path);
+    let resume_from = metadata(&part_path).await.map_or(0, |m| m.len());
+
+    // Being rate limited does not count as a failed attempt, the request is sent again once the
+    // pause is over.
+    let mut rate_limited = 0;
+    let mut response = loop {
+        select! {
+            () = options.pause.wait() => {}
+            () = options.cancelled() => eyre::bail!("Interrupted"),
+        }
+        let response = with_retry(options.attempts, || async {
+            let mut request = client.get(url.clone());
+            if resume_from > 0 {
+                request = request
+                    .header(RANGE, format!("bytes={resume_from}-"))
+                    .header(ACCEPT_ENCODING, "identity");
+            }
+            let mut response = request.send().await?;
+            if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+                // The partial file does not match the remote one, so start over.
+                response = client.get(url.clone()).send().await?;
+            }
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                return Ok(response);
+            }
+            response.error_for_status()
+        })
+        .await?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            break response;
+        }
+
+        rate_limited += 1;
+        let delay = retry_after(response.headers()).unwrap_or_else(|| backoff(rate_limited));
+        warn!(
+            file = file_name,
+            "Rate limited by the server, pausing all downloads for {}",
+            HumanDuration(delay)
+        );
+        options.pause.pause(delay);
+    };
+
+    let size = response.content_length().map(|len| {
+        if response.status() == StatusCode::PARTIAL_CONTENT {
+            resume_from + len
+        } else {
+            len
+        }
+    });
+    if let Some(size) = size.filter(|&size| !options.size_allowed(size)) {
+        info!(
+            file = file_name,
+            size, "Skipped, the size is out of the allowed range"
+        );
+        return Ok(Fetched::OutOfRange);
+    }
+    // Better not to start a file known not to fit, than to leave it half downloaded.
+    if let (Some(quota), Some(len)) = (&options.quota, response.content_length()) {
+        quota.check(len)?;
+    }
+
+    // The server may ignore the range and send the whole file, so only append on 206.
+    // The digest is computed while downloading, starting with what was downloaded before.
+    let mut hasher = Sha256::new();
+    let (file, downloaded) = if response.status() == StatusCode::PARTIAL_CONTENT {
+        let downloaded = hash_file(&part_path, &mut hasher).await?;
+        (
+            OpenOptions::new().append(true).open(&part_path).await?,
+            downloaded,
+        )
+    } else {
+        (File::create(&part_path).await?, 0)
+    };
+    let mut file = BufWriter::new(file);
+
+    let resumable = response.status() == StatusCode::PARTIAL_CONTENT
+        || response
+            .headers()
+            .get(ACCEPT_RANGES)
+            .is_some_and(|v| v.as_bytes() == b"bytes");
+
+    let mut progress = FileProgress::new(
+        options.progress,
+        multi_progress,
+        url.as_str(),
+        file_name,
+        options.name_width,
+        downloaded,
+        response.content_length().map(|len| downloaded + len),
+    );
+    let result = write_body(
+        &mut response,
+        &mut file,
+        &mut hasher,
+        &mut progress,
+        options,
+    )
+    .await;
+    progress.finish();
+    let received = match result {
+        Ok(received) => received,
+        Err(e) => {
+            // Keep the partial file only if the next run can pick up where this one left off, and
+            // the downloads were not interrupted on purpose.
+            if resumable && !options.is_cancelled() {
+                let _ = file.flush().await;
+            } else {
+                drop(file);
+                let _ = remove_file(&part_path).await;
+            }
+            return Err(e);
+        }
+    };
+
+    drop(file);
+
+    // The name given by the server is usually more accurate than the one in the URL, but should
+    // not replace another file.
+    let path = response
+        .headers()
+        .get(CONTENT_DISPOSITION)
+        .and_then(content_disposition_name)
+        .and_then(|name| path_with_name(path, file_name, &name))
+        .filter(|named| named == path || !named.exists())
+        .unwrap_or_else(|| path.clone());
+    let path = path_for_content_type(&path, response.headers().get(CONTENT_TYPE)).unwrap_or(path);
+
+    if is_pdf(&path) && !has_pdf_magic(&part_path).await? {
+        remove_file(&part_path).await?;
+        eyre::bail!("The file is not a PDF, the server may have sent an error page instead");
+    }
+
+    rename(&part_path, &path).await?;
+
+    Ok(Fetched::Downloaded(
+        path,
+        (downloaded + received, hex_digest(hasher)),
+    ))
+}
+
+/// The file name given by a `Content-Disposition` header, preferring the RFC 5987 form like
+/// `filename*=UTF-8''%E7%AC%AC1%E5%91%A8.pdf` to the plain `filename`.
+///
+/// Both forms may be percent-encoded, and in GBK instead of UTF-8, like file names in URLs. The
+/// RFC 5987 form is decoded with the charset it names, and ignored if the charset is unknown.
+/// Folders before the name are dropped.
+fn content_disposition_name(value: &HeaderValue) -> Option<String> {
+    let mut params = Vec::new();
+    let mut rest = value.as_bytes();
+    while !rest.is_empty() {
+        // Split on `;` outside of quoted strings.
+        let mut end = 0;
+        let mut quoted = false;
+        while end < rest.len() {
+            match rest[end] {
+                b'\\' if quoted => end += 1,
+                b'"' => quoted = !quoted,
+                b';' if !quoted => break,
+                _ => {}
+            }
+            end += 1;
+        }
+        let param = &rest[..end.min(rest.len())];
+        rest = rest.get(end + 1..).unwrap_or_default();
+        if let Some(eq) = memchr(b'=', param) {
+            let key = String::from_utf8_lossy(param[..eq].trim_ascii()).to_ascii_lowercase();
+            params.push((key, param[eq + 1..].trim_ascii()));
+        }
+    }
+
+    let extended = params
+        .iter()
+        .find(|(key, _)| key == "filename*")
+        .and_then(|(_, value)| {
+            // Like `UTF-8'zh-CN'...`, the charset and language before the encoded name.
+            let mut parts = value.splitn(3, |&b| b == b'\'');
+            let (charset, _, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+            // An unknown charset leaves the plain `filename` to be used instead.
+            let encoding = Encoding::for_label(charset)?;
+            Some(if encoding == UTF_8 {
+                unquote_lossy(encoded)
+            } else {
+                let bytes = unquote_to_bytes(encoded);
+                encoding.decode_without_bom_handling(&bytes).0.into_owned()
+            })
+        });
+    let name = extended.or_else(|| {
+        let (_, value) = params.iter().find(|(key, _)| key == "filename")?;
+        let value = match value
+            .strip_prefix(b"\"")
+            .and_then(|v| v.strip_suffix(b"\""))
+        {
+            Some(quoted) => {
+                let mut unescaped = Vec::with_capacity(quoted.len());
+                let mut bytes = quoted.iter();
+                while let Some(&b) = bytes.next() {
+                    unescaped.push(if b == b'\\' { *bytes.next()? } else { b });
+                }
+                unescaped
+            }
+            None => value.to_vec(),
+        };
+        Some(unquote_lossy(value))
+    })?;
+
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// `path` with `name` instead of `file_name`, where the file name ends the last part of `path`.
+///
+/// Returns [`None`] when the file name was changed while planning the path, as for duplicates,
+/// or is followed by more text of a template.
+fn path_with_name(path: &Path, file_name: &str, name: &str) -> Option<PathBuf> {
+    let last = path.file_name()?.to_str()?;
+    let before = last.strip_suffix(&sanitize_file_name(file_name))?;
+    Some(path.with_file_name(format!("{before}{}", sanitize_file_name(name))))
+}
+
+/// The extensions of the types of files usually found in courses.
+const CONTENT_TYPE_EXTENSIONS: &[(&str, &str)] = &[
+    ("application/pdf", "pdf"),
+    ("application/msword", "doc"),
+    (
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "docx",
+    ),
+    ("application/vnd.ms-powerpoint", "ppt"),
+    (
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "pptx",
+    ),
+    ("application/vnd.ms-excel", "xls"),
+    (
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "xlsx",
+    ),
+    ("application/zip", "zip"),
+    ("application/vnd.rar", "rar"),
+    ("application/x-rar-compressed", "rar"),
+    ("text/plain", "txt"),
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("video/mp4", "mp4"),
+];
+
+/// Extensions which tell nothing about what a file holds.
+const GENERIC_EXTENSIONS: &[&str] = &["bin", "dat", "tmp"];
+
+/// The path with the extension matching `content_type`, if `path` has none or a generic one.
+///
+/// Anything after the last `.` which does not look like an extension, as in `1.1 Introduction`,
+/// is kept as part of the name.
+fn path_for_content_type(path: &Path, content_type: Option<&HeaderValue>) -> Option<PathBuf> {
+    let content_type = content_type?.to_str().ok()?;
+    let mime = content_type.split(';').next()?.trim();
+    let (_, extension) = CONTENT_TYPE_EXTENSIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(mime))?;
+
+    let current = path
+        .extension()
+        .and_then(|current| current.to_str())
+        .filter(|current| current.len() <= 5 && current.chars().all(|c| c.is_ascii_alphanumeric()));
+    match current {
+        Some(current) if GENERIC_EXTENSIONS.contains(&current.to_ascii_lowercase().as_str()) => {
+            Some(path.with_extension(extension))
+        }
+        Some(_) => None,
+        None => {
+            let mut path = path.as_os_str().to_owned();
+            path.push(".");
+            path.push(extension);
+            Some(path.into())
+        }
+    }
+}
+
+/// The path with ` (n)` added to the file name before its extension, like `notes (1).pdf`.
+///
+/// Any number already added to `path` is replaced.
+fn numbered_path(path: &Path, n: u32) -> PathBuf {
+    static REGEX: LazyLock<regex::Regex> =
+        LazyLock::new(|| regex::Regex::new(r" \([0-9]+\)$").unwrap());
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let stem = REGEX.replace(&stem, "");
+    let mut file_name = format!("{stem} ({n})");
+    if let Some(extension) = path.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(file_name)
+}
+
+fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+/// Check whether a file starts with the `%PDF-` header, reading only the first bytes.
+async fn has_pdf_magic(path: &Path) -> eyre::Result<bool> {
+    const MAGIC: &[u8] = b"%PDF-";
+
+    let mut header = Vec::with_capacity(MAGIC.len());
+    File::open(path)
+        .await?
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut header)
+        .await?;
+
+    Ok(header == MAGIC)
+}
+
+/// The size told by the response to a HEAD request.
+///
+/// [`Response::content_length`] gives the size of the body, which is always empty for HEAD, so
+/// the header is read instead.
+fn head_content_length(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Wait for `timeout`, or forever without one.
+async fn idle(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => sleep(timeout).await,
+        None => pending().await,
+    }
+}
+
+/// Write the whole response body to `file`, feeding it to `hasher` as well, and flush it.
+/// Returns the number of bytes written.
+///
+/// Fails if the connection is closed before the length told by the server is received, so that
+/// a truncated file is not taken for a complete one.
+async fn write_body(
+    response: &mut Response,
+    file: &mut BufWriter<File>,
+    hasher: &mut Sha256,
+    progress: &mut FileProgress,
+    options: &DownloadOptions,
+) -> eyre::Result<u64> {
+    let expected = response.content_length();
+    let mut received = 0;
+    loop {
+        let chunk = select! {
+            chunk = response.chunk() => chunk?,
+            () = idle(options.idle_timeout) => {
+                let timeout = options.idle_timeout.unwrap_or_default();
+                eyre::bail!("No data received for {}", HumanDuration(timeout))
+            }
+            () = options.cancelled() => eyre::bail!("Interrupted"),
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        if let Some(rate_limiter) = &options.rate_limiter {
+            rate_limiter.acquire(chunk.len() as u64).await;
+        }
+        if let Some(quota) = &options.quota {
+            quota.consume(chunk.len() as u64)?;
+        }
+        progress.inc(chunk.len() as u64);
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    if let Some(expected) = expected.filter(|&expected| expected != received) {
+        eyre::bail!(
+            "The connection was closed early, received {} of {}",
+            HumanBytes(received),
+            HumanBytes(expected)
+        );
+    }
+
+    Ok(received)
+}
+
+/// The name of the file behind `url`, taken from its `download` query parameter.
+pub fn file_name(url: &Url) -> Option<String> {
+    // The name may be percent-encoded twice, and in GBK instead of UTF-8, so it is decoded with
+    // `parse_query_string` rather than `Url::query_pairs`, which would replace GBK bytes.
+    let (_, name) = parse_query_string(url.query()?)
+        .into_iter()
+        .find(|(key, _)| key == "download")?;
+    Some(unquote_plus_lossy(name))
+}
+
+/// Whether the file behind `url` has one of `extensions`, see [`get_pdf_urls`].
+fn has_extension(url: &Url, extensions: &[String]) -> bool {
+    let name = file_name(url).unwrap_or_else(|| url.path().to_string());
+    let extension = Path::new(&name).extension().unwrap_or_default();
+
+    extensions.iter().any(|expected| {
+        expected == "*" || extension.eq_ignore_ascii_case(expected.trim_start_matches('.'))
+    })
+}
+
+/// The hosts known to serve course files over HTTPS as well, along with their subdomains.
+const HTTPS_HOSTS: &[&str] = &["nos.netease.com", "nosdn.127.net", "icourse163.org"];
+
+/// Switch `url` from HTTP to HTTPS if its host is known to support it, returning whether it was
+/// changed.
+pub fn upgrade_https(url: &mut Url) -> bool {
+    let known = url.host_str().is_some_and(|host| {
+        HTTPS_HOSTS
+            .iter()
+            .any(|known| host == *known || host.ends_with(&format!(".{known}")))
+    });
+    url.scheme() == "http" && known && url.set_scheme("https").is_ok()
+}
+
+/// A rewrite of the host of file URLs, like `nos.netease.com=mirror.example.com`, to download
+/// them from a mirror of the CDN.
+///
+/// The new host may come with a port, and a scheme like `http://mirror.example.com:8080`,
+/// otherwise those of the URLs are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostRewrite {
+    pub from: String,
+    pub scheme: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl HostRewrite {
+    /// Rewrite `url` if its host is [`Self::from`], returning whether it was changed.
+    pub fn apply(&self, url: &mut Url) -> bool {
+        if !url
+            .host_str()
+            .is_some_and(|host| host.eq_ignore_ascii_case(&self.from))
+        {
+            return false;
+        }
+        let original = url.clone();
+        if let Some(scheme) = &self.scheme {
+            // Only fails between special and non-special schemes, which the parsing rules out.
+            let _ = url.set_scheme(scheme);
+        }
+        if url.set_host(Some(&self.host)).is_err() {
+            *url = original;
+            return false;
+        }
+        if self.port.is_some() {
+            let _ = url.set_port(self.port);
+        }
+        *url != original
+    }
+}
+
+impl FromStr for HostRewrite {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| eyre::eyre!("Expected a rewrite like `FROM=TO`, got `{s}`"))?;
+        let from = from.trim().to_ascii_lowercase();
+        if from.is_empty() || from.contains(['/', ':']) {
+            eyre::bail!("Expected a host to rewrite like `nos.netease.com`, got `{from}`");
+        }
+
+        let to = to.trim();
+        let (scheme, url) = match to.split_once("://") {
+            Some((scheme, _)) => (Some(scheme.to_ascii_lowercase()), Url::parse(to)),
+            None => (None, Url::parse(&format!("https://{to}"))),
+        };
+        let url = url.wrap_err_with(|| format!("Invalid host to rewrite to `{to}`"))?;
+        if !matches!(scheme.as_deref(), None | Some("http" | "https")) || url.path() != "/" {
+            eyre::bail!("Expected a host like `mirror.example.com:8080`, got `{to}`");
+        }
+        let host = url.host_str().ok_or_eyre("No host to rewrite to")?;
+
+        Ok(Self {
+            from,
+            scheme,
+            host: host.to_string(),
+            port: url.port(),
+        })
+    }
+}
+
+/// Replace characters not allowed in file names on common platforms.
+pub fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows does not allow trailing dots or spaces.
+    let name = name.trim().trim_end_matches('.');
+    match name {
+        "" => "_".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// The path a file is written to while it is being downloaded.
+fn part_path(path: &Path) -> PathBuf {
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(".part");
+    part_path.into()
+}
+
+/// The manifest written into `path` by an earlier run, or an empty one if it cannot be read.
+async fn previous_manifest(path: &Path) -> Manifest {
+    let manifest_path = path.join(Manifest::FILE_NAME);
+    Manifest::load(&manifest_path).await.unwrap_or_else(|e| {
+        debug!(path = %manifest_path.display(), "Failed to read the manifest: {e:#}");
+        Manifest::default()
+    })
+}
+
+/// The paths at which earlier runs saved the files listed in `manifest`, relative to its
+/// directory, by URL.
+fn saved_paths(manifest: &Manifest) -> HashMap<String, PathBuf> {
+    manifest
+        .files
+        .iter()
+        .filter(|entry| matches!(entry.status, FileStatus::Downloaded { .. }))
+        .map(|entry| (entry.url.clone(), PathBuf::from(&entry.path)))
+        .collect()
+}
+
+/// What to record in the manifest for the file kept as it is at `path`.
+///
+/// Its entry in `previous`, by path, is reused while the size still matches, so that the file is
+/// not read again and keeps the time it was downloaded. Otherwise its digest is computed, and it
+/// is taken as downloaded when last modified.
+async fn kept_status(
+    path: &Path,
+    previous: &HashMap<PathBuf, FileStatus>,
+) -> eyre::Result<FileStatus> {
+    let metadata = metadata(path).await?;
+    if let Some(status @ FileStatus::Downloaded { size, .. }) = previous.get(path) {
+        if *size == metadata.len() {
+            return Ok(status.clone());
+        }
+    }
+    let (size, sha256) = file_digest(path).await?;
+    let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+    Ok(FileStatus::Downloaded {
+        size,
+        sha256,
+        downloaded_at: modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+    })
+}
+
+/// Where each of `resources` is saved by [`download`], relative to the output directory, along
+/// with its file name.
+///
+/// Files which an earlier run saved under the name given by the server, as listed in `saved`
+/// (see [`saved_paths`]), keep that name, so that they are found again.
+fn plan(
+    resources: &[Resource],
+    options: &DownloadOptions,
+    saved: &HashMap<String, PathBuf>,
+) -> eyre::Result<Vec<(PathBuf, String)>> {
+    let index_width = resources.len().to_string().len();
+
+    let mut plan = resources
+        .iter()
+        .enumerate()
+        .map(|(index, resource)| {
+            // The name in `Content-Disposition` may be used instead, once downloaded.
+            let file_name = file_name(&resource.url)
+                .or_else(|| {
+                    let last = resource.url.path_segments()?.next_back()?;
+                    (!last.is_empty()).then(|| unquote_lossy(last))
+                })
+                .ok_or_eyre("No filename found in URL")?;
+            let chapter_name = &resource.chapter_name;
+            let section_name = &resource.section_name;
+
+            let relative = match &options.template {
+                Some(template) => template.expand(|placeholder| match placeholder {
+                    Placeholder::Chapter => chapter_name.clone(),
+                    Placeholder::Section => section_name.clone(),
+                    Placeholder::FileName => Some(file_name.clone()),
+                    Placeholder::Index => Some(format!("{:0index_width$}", index + 1)),
+                    Placeholder::Tid | Placeholder::Course => None,
+                }),
+                None => {
+                    let mut relative = PathBuf::new();
+                    if !options.flat {
+                        relative.extend(
+                            [chapter_name, section_name]
+                                .into_iter()
+                                .flatten()
+                                .map(|name| sanitize_file_name(name)),
+                        );
+                    }
+                    let name = sanitize_file_name(&file_name);
+                    if options.number {
+                        relative.push(format!("{:0index_width$}-{name}", index + 1));
+                    } else {
+                        relative.push(name);
+                    }
+                    relative
+                }
+            };
+            Ok((relative, file_name))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    resolve_duplicates(&mut plan, resources, options.on_duplicate)?;
+
+    let key = |path: &Path| path.to_string_lossy().to_lowercase();
+    for (i, resource) in resources.iter().enumerate() {
+        let Some(saved) = saved.get(resource.url.as_str()) else {
+            continue;
+        };
+        let (relative, file_name) = &plan[i];
+        let taken = plan.iter().any(|(other, _)| key(other) == key(saved));
+        if !taken && is_renamed(relative, file_name, saved) {
+            plan[i].0 = saved.clone();
+        }
+    }
+    Ok(plan)
+}
+
+/// Whether `saved` may be the file planned at `path` after [`download_file`] renamed it, by the
+/// name or the type given by the server.
+fn is_renamed(path: &Path, file_name: &str, saved: &Path) -> bool {
+    let before = path
+        .file_name()
+        .and_then(|last| last.to_str())
+        .and_then(|last| last.strip_suffix(&sanitize_file_name(file_name)));
+    let saved_name = saved.file_name().and_then(|last| last.to_str());
+    match (before, saved_name) {
+        (Some(before), Some(saved_name)) => {
+            saved.parent() == path.parent() && saved_name.starts_with(before)
+        }
+        _ => false,
+    }
+}
+
+/// Rename the files in `plan` saved at the same path as another, as told by `on_duplicate`.
+///
+/// Paths are compared ignoring case, as on Windows and macOS. Files still sharing a path after
+/// that are numbered like `notes (1).pdf`, in the order of the course.
+fn resolve_duplicates(
+    plan: &mut [(PathBuf, String)],
+    resources: &[Resource],
+    on_duplicate: OnDuplicate,
+) -> eyre::Result<()> {
+    let key = |path: &Path| path.to_string_lossy().to_lowercase();
+    let duplicates = |plan: &[(PathBuf, String)]| {
+        let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, (relative, _)) in plan.iter().enumerate() {
+            groups.entry(key(relative)).or_default().push(i);
+        }
+        let mut duplicates: Vec<_> = groups.into_values().filter(|g| g.len() > 1).collect();
+        duplicates.sort();
+        duplicates
+    };
+
+    let groups = duplicates(plan);
+    if groups.is_empty() {
+        return Ok(());
+    }
+
+    let chapters: Vec<_> = resources
+        .iter()
+        .map(|resource| &resource.chapter_name)
+        .fold(Vec::new(), |mut chapters, name| {
+            if !chapters.contains(&name) {
+                chapters.push(name);
+            }
+            chapters
+        });
+    let chapter_width = chapters.len().to_string().len();
+
+    for i in groups.into_iter().flatten() {
+        let resource = &resources[i];
+        let prefix = match on_duplicate {
+            OnDuplicate::Chapter => {
+                let chapter = chapters
+                    .iter()
+                    .position(|&name| *name == resource.chapter_name)
+                    .unwrap_or_default();
+                format!("{:0chapter_width$}", chapter + 1)
+            }
+            OnDuplicate::Section => match &resource.section_name {
+                Some(section) => sanitize_file_name(section),
+                None => continue,
+            },
+            OnDuplicate::Error => eyre::bail!(
+                "More than one file would be saved as {}, pass --on-duplicate to rename them",
+                plan[i].0.display()
+            ),
+        };
+        let relative = &mut plan[i].0;
+        let name = relative.file_name().unwrap_or_default().to_string_lossy();
+        let name = format!("{prefix} - {name}");
+        relative.set_file_name(name);
+    }
+
+    for group in duplicates(plan) {
+        for (n, i) in group.into_iter().enumerate().skip(1) {
+            plan[i].0 = numbered_path(&plan[i].0, n as u32);
+        }
+    }
+
+    Ok(())
+}
+
+/// How a file on disk compares to the one on the server, see [`verify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileCheck {
+    /// The file exists, with the size given by the server.
+    Ok,
+    /// The file does not exist.
+    Missing,
+    /// The file exists with another size, or the server did not tell the size.
+    SizeMismatch { local: u64, remote: Option<u64> },
+}
+
+impl Display for FileCheck {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => f.write_str("ok"),
+            Self::Missing => f.write_str("missing"),
+            Self::SizeMismatch {
+                local,
+                remote: Some(remote),
+            } => write!(f, "{local} bytes, but {remote} bytes on the server"),
+            Self::SizeMismatch {
+                local,
+                remote: None,
+            } => write!(f, "{local} bytes, but unknown size on the server"),
+        }
+    }
+}
+
+/// The files of `resources` which [`download`] would save into `path` and which already exist, in
+/// the order of `resources`.
+pub async fn existing_files(
+    resources: &[Resource],
+    path: &Path,
+    options: &DownloadOptions,
+) -> eyre::Result<Vec<PathBuf>> {
+    let saved = saved_paths(&previous_manifest(path).await);
+    Ok(plan(resources, options, &saved)?
+        .into_iter()
+        .map(|(relative, _)| path.join(relative))
+        .filter(|path| path.is_file())
+        .collect())
+}
+
+/// Check the files saved into `path` by [`download`] against the server, by comparing their
+/// sizes with the `Content-Length` of HEAD requests.
+///
+/// Returns the path of each file relative to `path`, in the order of `resources`.
+pub async fn verify<P: AsRef<Path>>(
+    client: &Client,
+    resources: &[Resource],
+    path: P,
+    options: &DownloadOptions,
+) -> eyre::Result<Vec<(PathBuf, FileCheck)>> {
+    let path = path.as_ref();
+    let mut join_set = JoinSet::new();
+
+    let saved = saved_paths(&previous_manifest(path).await);
+    let plan = plan(resources, options, &saved)?;
+    for (index, (resource, (relative, _))) in resources.iter().zip(plan).enumerate() {
+        let client = client.clone();
+        let options = options.clone();
+        let url = resource.url.clone();
+        let path = path.join(&relative);
+
+        join_set.spawn(async move {
+            let check = async {
+                let Ok(local) = metadata(&path).await else {
+                    return eyre::Ok(FileCheck::Missing);
+                };
+                let local = local.len();
+
+                let _permit = options.concurrency.acquire(&url).await?;
+                let response = with_retry(options.attempts, || async {
+                    client
+                        .head(url.clone())
+                        .header(ACCEPT_ENCODING, "identity")
+                        .send()
+                        .await?
+                        .error_for_status()
+                })
+                .await?;
+                Ok(match head_content_length(&response) {
+                    Some(remote) if remote == local => FileCheck::Ok,
+                    remote => FileCheck::SizeMismatch { local, remote },
+                })
+            }
+            .await
+            .wrap_err_with(|| format!("Failed to check {} against {url}", relative.display()));
+
+            (index, relative, check)
+        });
+    }
+
+    let mut checks = Vec::new();
+    while let Some(res) = join_set.join_next().await {
+        let (index, relative, check) = res?;
+        checks.push((index, relative, check?));
+    }
+    checks.sort_by_key(|(index, ..)| *index);
+
+    Ok(checks
+        .into_iter()
+        .map(|(_, relative, check)| (relative, check))
+        .collect())
+}
+
+/// Download `resources` into the directory `path`, and write a `manifest.json` listing them.
+///
+/// Failing files do not stop the others, and are reported together at the end, along with the
+/// [`DownloadReport`] of the others.
+pub async fn download<P: AsRef<Path>>(
+    client: &Client,
+    resources: impl IntoIterator<Item = Resource>,
+    path: P,
+    options: &DownloadOptions,
+    multi_progress: &MultiProgress,
+) -> eyre::Result<DownloadReport> {
+    let path = path.as_ref();
+    create_dir_all(&path).await?;
+    let mut join_set = JoinSet::new();
+
+    let resources: Vec<_> = resources.into_iter().collect();
+    // Planned before filtering, so that the names of the files do not depend on the filter.
+    let previous = previous_manifest(path).await;
+    let plan = plan(&resources, options, &saved_paths(&previous))?;
+    // The entries of files kept as they are, to be reused rather than reading the files again.
+    let previous: Arc<HashMap<_, _>> = Arc::new(
+        previous
+            .files
+            .into_iter()
+            .filter(|entry| matches!(entry.status, FileStatus::Downloaded { .. }))
+            .map(|entry| (path.join(&entry.path), entry.status))
+            .collect(),
+    );
+    let files: Vec<_> = resources
+        .into_iter()
+        .zip(plan)
+        .filter(|(resource, (_, file_name))| {
+            let matches = options.filter.matches(file_name);
+            if !matches {
+                debug!(url = %resource.url, "Filtered out {file_name}");
+            }
+            matches
+        })
+        .collect();
+    let total = files.len();
+    let archive = match &options.zip {
+        Some(zip) => Some(Arc::new(Archive::create(zip)?)),
+        None => None,
+    };
+
+    let overall = match options.progress {
+        ProgressMode::Bar => multi_progress.add(ProgressBar::new(total as u64).with_style(
+            ProgressStyle::with_template("{wide_bar} {pos}/{len} files, {msg}").unwrap(),
+        )),
+        ProgressMode::Plain | ProgressMode::Json => ProgressBar::hidden(),
+    };
+    let started = Instant::now();
+    let mut downloaded = 0;
+    let mut skipped = 0;
+    // The files skipped as they did not fit in the quota.
+    let mut over_quota = 0;
+    let mut total_size = 0;
+    overall.set_message(BinaryBytes(total_size).to_string());
+
+    // Make sure all the URLs are downloaded concurrently until completion or error
+    for (index, (Resource { url, .. }, (relative, file_name))) in files.into_iter().enumerate() {
+        let client = client.clone();
+        let multi_progress = multi_progress.clone();
+        let options = options.clone();
+        let archive = archive.clone();
+        let previous = previous.clone();
+        let root = path.to_path_buf();
+        let path = path.join(&relative);
+
+        join_set.spawn(async move {
+            let result = async {
+                let _permit = options.concurrency.acquire(&url).await?;
+                if options.is_cancelled() {
+                    eyre::bail!("Interrupted");
+                }
+                if let Some(quota) = &options.quota {
+                    quota.check(0)?;
+                }
+                info!(file = %path.display(), %url, "Downloading");
+                create_dir_all(path.parent().unwrap_or(&path)).await?;
+                let mut attempt = 1;
+                let fetched = loop {
+                    let result = download_file(
+                        &client,
+                        url.clone(),
+                        &path,
+                        &file_name,
+                        &options,
+                        &previous,
+                        &multi_progress,
+                    )
+                    .await;
+                    match result {
+                        Ok(fetched) => break fetched,
+                        Err(e)
+                            if attempt <= options.file_retries
+                                && !options.is_cancelled()
+                                && e.downcast_ref::<QuotaReached>().is_none() =>
+                        {
+                            warn!(file = %path.display(), attempt, "{e:#}, retrying");
+                            sleep(backoff(attempt)).await;
+                            attempt += 1;
+                        }
+                        Err(e) => {
+                            return Err(e.wrap_err(format!("Gave up after {attempt} attempt(s)")))
+                        }
+                    }
+                };
+                if let (Some(archive), Fetched::Downloaded(path, _) | Fetched::Existing(path, _)) =
+                    (archive, &fetched)
+                {
+                    let name = display_relative(path.strip_prefix(&root)?);
+                    let path = path.clone();
+                    spawn_blocking(move || archive.add_file(&name, &path)).await??;
+                }
+                eyre::Ok(fetched)
+            }
+            .await
+            .wrap_err_with(|| format!("Failed to download {file_name} from {url}"));
+
+            (index, relative, url, result)
+        });
+    }
+
+    let mut errors = Vec::new();
+    let mut manifest = Manifest::default();
+    let mut paths = Vec::new();
+    let mut left_out = Vec::new();
+    let mut existing = Vec::new();
+
+    while let Some(res) = join_set.join_next().await {
+        overall.inc(1);
+        let (index, mut relative, url, result) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                errors.push(e.into());
+                continue;
+            }
+        };
+
+        let status = match result {
+            Ok(Fetched::OutOfRange) => {
+                skipped += 1;
+                if options.progress == ProgressMode::Json {
+                    Event::Skipped { url: url.as_str() }.emit();
+                }
+                left_out.push(url.clone());
+                FileStatus::Skipped {
+                    reason: "size out of the allowed range".to_string(),
+                }
+            }
+            Err(e) if e.downcast_ref::<QuotaReached>().is_some() => {
+                skipped += 1;
+                over_quota += 1;
+                debug!(file = %relative.display(), "{e:#}");
+                if options.progress == ProgressMode::Json {
+                    Event::Skipped { url: url.as_str() }.emit();
+                }
+                left_out.push(url.clone());
+                FileStatus::Skipped {
+                    reason: "quota of downloaded bytes reached".to_string(),
+                }
+            }
+            Ok(Fetched::Existing(saved_path, status)) => {
+                // Counted as skipped, so that a run with nothing new does not look like it
+                // downloaded the whole course again.
+                skipped += 1;
+                if let Ok(saved) = saved_path.strip_prefix(path) {
+                    relative = saved.to_path_buf();
+                }
+                paths.push((index, saved_path));
+                if options.progress == ProgressMode::Json {
+                    Event::Skipped { url: url.as_str() }.emit();
+                }
+                existing.push(url.clone());
+                status
+            }
+            Ok(Fetched::Downloaded(saved_path, (size, sha256))) => {
+                // The file may have been renamed to avoid overwriting another one.
+                if let Ok(saved) = saved_path.strip_prefix(path) {
+                    relative = saved.to_path_buf();
+                }
+                paths.push((index, saved_path));
+                info!(file = %relative.display(), size, "Downloaded");
+                match options.progress {
+                    ProgressMode::Bar => {}
+                    ProgressMode::Plain => println!(
+                        "[{}/{total}] {} ({})",
+                        overall.position(),
+                        relative.display(),
+                        HumanBytes(size)
+                    ),
+                    ProgressMode::Json => Event::Finished {
+                        url: url.as_str(),
+                        path: &display_relative(&relative),
+                        size,
+                    }
+                    .emit(),
+                }
+                downloaded += 1;
+                total_size += size;
+                overall.set_message(BinaryBytes(total_size).to_string());
+                FileStatus::Downloaded {
+                    size,
+                    sha256,
+                    downloaded_at: SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)?
+                        .as_secs(),
+                }
+            }
+            Err(e) => {
+                error!(file = %relative.display(), "{e:#}");
+                if options.progress == ProgressMode::Json {
+                    Event::Failed {
+                        url: url.as_str(),
+                        error: format!("{e:#}"),
+                    }
+                    .emit();
+                }
+                let status = FileStatus::Failed {
+                    error: format!("{e:#}"),
+                };
+                errors.push(e);
+                status
+            }
+        };
+
+        manifest.files.push(ManifestEntry {
+            path: display_relative(&relative),
+            url: url.to_string(),
+            status,
+        });
+    }
+
+    // Printed to stdout in plain mode, and in place of the progress bar on stderr otherwise.
+    let style = |color| {
+        let style = Style::new().fg(color);
+        match options.progress {
+            ProgressMode::Plain => style,
+            ProgressMode::Bar | ProgressMode::Json => style.for_stderr(),
+        }
+    };
+    let mut summary = format!(
+        "{} {downloaded} file(s) ({}) in {}",
+        style(Color::Green).apply_to("Downloaded"),
+        HumanBytes(total_size),
+        HumanDuration(started.elapsed())
+    );
+    if skipped > 0 {
+        let skipped = format!("{skipped} skipped");
+        summary.push_str(&format!(", {}", style(Color::Yellow).apply_to(skipped)));
+    }
+    if !errors.is_empty() {
+        let failed = format!("{} failed", errors.len());
+        summary.push_str(&format!(", {}", style(Color::Red).apply_to(failed)));
+    }
+    if options.progress == ProgressMode::Plain {
+        println!("{summary}");
+    }
+    overall.set_style(ProgressStyle::with_template("{msg}").unwrap());
+    overall.finish_with_message(summary);
+    if let Some(quota) = options.quota.as_ref().filter(|_| over_quota > 0) {
+        warn!(
+            "{over_quota} file(s) left out, as the quota of {} was reached",
+            HumanBytes(quota.limit())
+        );
+    }
+
+    // Files downloaded by earlier runs are kept in the manifest, so that they can be checked
+    // with `verify_digests` as well.
+    manifest.files.sort_by(|a, b| a.path.cmp(&b.path));
+    let manifest_path = path.join(Manifest::FILE_NAME);
+    let mut saved = manifest.clone();
+    match Manifest::load(&manifest_path).await {
+        Ok(previous) => saved.merge(previous),
+        Err(e) => {
+            warn!(path = %manifest_path.display(), "Failed to read the previous manifest: {e:#}");
+        }
+    }
+    saved.save(&manifest_path).await?;
+
+    if let Some(archive) = archive {
+        let archive = Arc::into_inner(archive).expect("all downloads have finished");
+        // An incomplete archive is of no use, the loose files are kept for the next run instead.
+        if errors.is_empty() {
+            let metadata_path = path.join(CourseMetadata::FILE_NAME);
+            spawn_blocking(move || {
+                archive.add_file("manifest.json", &manifest_path)?;
+                if metadata_path.is_file() {
+                    archive.add_file(CourseMetadata::FILE_NAME, &metadata_path)?;
+                }
+                archive.finish()
+            })
+            .await??;
+        } else {
+            spawn_blocking(move || archive.discard()).await??;
+        }
+    }
+
+    paths.sort_by_key(|(index, _)| *index);
+    let report = DownloadReport {
+        paths: paths.into_iter().map(|(_, path)| path).collect(),
+        manifest,
+        left_out,
+        existing,
+    };
+    if !errors.is_empty() {
+        return Err(FailedFiles {
+            total,
+            errors,
+            report,
+        }
+        .into());
+    }
+
+    Ok(report)
+}
+
+/// What [`download`] did.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadReport {
+    /// Where the files downloaded, or found to exist already, are saved, in the order of the
+    /// course.
+    pub paths: Vec<PathBuf>,
+    /// What happened to each file, as added to the manifest.
+    pub manifest: Manifest,
+    /// The URLs of the files skipped as they did not fit in the quota or the allowed sizes, which
+    /// are left for a later run.
+    pub left_out: Vec<Url>,
+    /// The URLs of the files kept as they already existed, which are still in [`Self::paths`] and
+    /// the manifest.
+    pub existing: Vec<Url>,
+}
+
+/// The error returned by [`download`] when some of the files failed to download, while the
+/// others were downloaded.
+#[derive(Debug)]
+pub struct FailedFiles {
+    /// The number of files to download, including the failed ones.
+    pub total: usize,
+    pub errors: Vec<eyre::Report>,
+    /// What happened to the files, like returned on success.
+    pub report: DownloadReport,
+}
+
+impl FailedFiles {
+    /// Whether no file at all was downloaded.
+    pub fn all_failed(&self) -> bool {
+        self.errors.len() == self.total
+    }
+}
+
+impl Display for FailedFiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} file(s) failed to download:",
+            self.errors.len(),
+            self.total
+        )?;
+        for e in &self.errors {
+            write!(f, "\n\n{e:?}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FailedFiles {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read as _, Write as _},
+        net::TcpListener,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use bytes::Bytes;
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+    use memchr::memmem::find;
+    use reqwest::{header::HeaderValue, Client, Url};
+    use tokio::{sync::watch, time::sleep};
+
+    use super::{
+        content_disposition_name, download, dwr::DwrParams, existing_files, file_name, file_urls,
+        get_course_metadata, get_course_name, get_ids, get_terms, has_extension, numbered_path,
+        parse_tid, path_for_content_type, path_with_name, resolve_duplicates, upgrade_https,
+        Concurrency, CourseMetadata, DownloadOptions, FailedFiles, FileFilter, FileStatus,
+        HostRewrite, Instructor, Manifest, OnDuplicate, OnExist, ProgressMode, Resource, Session,
+        Terms,
+    };
+
+    /// A reply of `getLastLearnedMocTermDto`, trimmed down to one section with two units.
+    const COURSE_INFO: &[u8] = br#"//#DWR-INSERT
+//#DWR-REPLY
+var s0={};var s1=[];var s2={};var s3=[];var s4={};var s5=[];var s6={};var s7={};
+s0.chapters=s1;s0.courseName="\u9AD8\u7B49\u6570\u5B66";s0.id=1470000000;
+s1[0]=s2;
+s2.contentId=null;s2.id=1001;s2.lessons=s3;s2.name="\u7B2C\u4E00\u5468";
+s3[0]=s4;
+s4.chapterId=1001;s4.contentId=null;s4.id=2001;s4.name="1.1 \u7EEA\u8BBA";s4.units=s5;
+s5[0]=s6;s5[1]=s7;
+s6.chapterId=1001;s6.contentId=3001;s6.contentType=1;s6.id=4001;s6.lessonId=2001;
+s7.chapterId=1001;s7.contentId=3002;s7.contentType=3;s7.id=4002;s7.lessonId=2001;
+dwr.engine._remoteHandleCallback('190','0',s0);
+"#;
+
+    #[test]
+    fn metadata() {
+        let reply = br#"//#DWR-REPLY
+var s0={};var s1={};var s2=[];var s3={};var s4={};
+s0.courseId=1002000000;s0.courseName="\u9AD8\u7B49\u6570\u5B66";s0.endTime=1704038400000;
+s0.id=1470000000;s0.lectorPanels=s2;s0.schoolPanel=s1;s0.startTime=1693497600000;
+s1.name="\u67D0\u5927\u5B66";s1.shortName="XX";
+s2[0]=s3;s2[1]=s4;
+s3.lectorTitle="\u6559\u6388";s3.realName="\u5F20\u4E09";
+s4.lectorTitle=null;s4.realName="\u674E\u56DB";
+"#;
+        let metadata = get_course_metadata(&Bytes::from_static(reply));
+        assert_eq!(
+            metadata,
+            CourseMetadata {
+                tid: Some("1470000000".to_string()),
+                course_id: Some("1002000000".to_string()),
+                course_name: Some("高等数学".to_string()),
+                school: Some("某大学".to_string()),
+                instructors: vec![
+                    Instructor {
+                        name: "张三".to_string(),
+                        title: Some("教授".to_string()),
+                    },
+                    Instructor {
+                        name: "李四".to_string(),
+                        title: None,
+                    },
+                ],
+                start_time: Some(1693497600000),
+                end_time: Some(1704038400000),
+            }
+        );
+
+        // Only what is found is kept.
+        let metadata = get_course_metadata(&Bytes::from_static(COURSE_INFO));
+        assert_eq!(metadata.course_name.as_deref(), Some("高等数学"));
+        assert_eq!(metadata.school, None);
+        assert!(metadata.instructors.is_empty());
+    }
+
+    #[test]
+    fn terms() {
+        assert_eq!(
+            get_terms(&Bytes::from_static(COURSE_INFO)),
+            Terms {
+                course_id: None,
+                tids: vec!["1470000000".to_string()]
+            }
+        );
+
+        let reply = br#"//#DWR-REPLY
+var s0={};var s1=[];var s2={};var s3={};
+s0.courseId=1002000000;s0.courseName="\u9AD8\u7B49\u6570\u5B66";s0.id=1470000000;s0.terms=s1;
+s1[0]=s2;s1[1]=s3;
+s2.termId=1460000000;s3.termId=1470000000;
+"#;
+        assert_eq!(
+            get_terms(&Bytes::from_static(reply)),
+            Terms {
+                course_id: Some("1002000000".to_string()),
+                tids: vec!["1460000000".to_string(), "1470000000".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn ids() {
+        let units = get_ids(&Bytes::from_static(COURSE_INFO));
+
+        let ids: Vec<_> = units
+            .iter()
+            .map(|u| (u.content_id.as_str(), u.section_id.as_str()))
+            .collect();
+        assert_eq!(ids, [("3001", "4001"), ("3002", "4002")]);
+        assert_eq!(units[1].chapter_name.as_deref(), Some("第一周"));
+        assert_eq!(units[1].section_name.as_deref(), Some("1.1 绪论"));
+    }
+
+    #[test]
+    fn ids_truncated() {
+        // Cut the reply right after the content ID of the last unit.
+        let end = find(COURSE_INFO, b"s7.contentType").unwrap();
+        let units = get_ids(&Bytes::copy_from_slice(&COURSE_INFO[..end]));
+
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].content_id, "3001");
+    }
+
+    #[test]
+    fn course_name() {
+        let course_info = Bytes::from_static(COURSE_INFO);
+        assert_eq!(get_course_name(&course_info).as_deref(), Some("高等数学"));
+
+        let course_info = Bytes::from_static(b"//#DWR-REPLY\ns0.courseName=null;s0.id=1;");
+        assert_eq!(get_course_name(&course_info), None);
+    }
+
+    #[test]
+    fn file_names() {
+        let name = |url| file_name(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            name("https://nos.netease.com/a.pdf?download=%25E7%25AC%25AC1%25E7%25AB%25A0.pdf")
+                .as_deref(),
+            Some("第1章.pdf")
+        );
+        assert_eq!(
+            name("https://nos.netease.com/a.pdf?download=%C4%E3%BA%C3+1.pdf").as_deref(),
+            Some("你好 1.pdf")
+        );
+        assert_eq!(name("https://nos.netease.com/a.pdf"), None);
+    }
+
+    #[test]
+    fn extensions() {
+        let url = Url::parse("https://nos.netease.com/a?download=%E8%AF%BE%E4%BB%B6.PPTX").unwrap();
+
+        assert!(has_extension(&url, &["pdf".into(), "pptx".into()]));
+        assert!(has_extension(&url, &[".pptx".into()]));
+        assert!(has_extension(&url, &["*".into()]));
+        assert!(!has_extension(&url, &["pdf".into()]));
+        assert!(!has_extension(&url, &[]));
+    }
+
+    #[test]
+    fn dwr_url() {
+        let session = Session {
+            site: Url::parse("https://school.icourse163.org").unwrap(),
+            id: String::new(),
+            dwr: DwrParams::default(),
+            pacer: None,
+        };
+        assert_eq!(
+            session.dwr_url("getLessonUnitLearnVo").unwrap().as_str(),
+            "https://school.icourse163.org/dwr/call/plaincall/CourseBean.getLessonUnitLearnVo.dwr"
+        );
+
+        let form = session.dwr_form(&session.dwr.course_info, &[("tid", "1470000000")]);
+        assert_eq!(form["c0-methodName"], "getLastLearnedMocTermDto");
+        assert_eq!(form["c0-param0"], "number:1470000000");
+        assert_eq!(form.get_index_of("batchId"), Some(form.len() - 1));
+    }
+
+    #[test]
+    fn tids() {
+        assert_eq!(parse_tid(" 1470000000 ").unwrap(), "1470000000");
+        assert_eq!(
+            parse_tid("https://www.icourse163.org/learn/XYZ-1234567?tid=1470000000#/learn/content")
+                .unwrap(),
+            "1470000000"
+        );
+        assert_eq!(
+            parse_tid("https://www.icourse163.org/spoc/learn/XYZ-1234567#/learn/announce?tid=147")
+                .unwrap(),
+            "147"
+        );
+        assert!(parse_tid("https://www.icourse163.org/course/XYZ-1234567").is_err());
+        assert!(parse_tid("XYZ-1234567").is_err());
+    }
+
+    #[test]
+    fn numbered_paths() {
+        let path = Path::new("download").join("notes.pdf");
+        assert_eq!(
+            numbered_path(&path, 1),
+            Path::new("download").join("notes (1).pdf")
+        );
+        assert_eq!(
+            numbered_path(&numbered_path(&path, 1), 2),
+            Path::new("download").join("notes (2).pdf")
+        );
+        assert_eq!(
+            numbered_path(Path::new("README"), 3),
+            PathBuf::from("README (3)")
+        );
+    }
+
+    #[test]
+    fn duplicates() {
+        let resource = |chapter: &str, section: &str| Resource {
+            url: Url::parse("https://nos.netease.com/notes.pdf").unwrap(),
+            content_id: None,
+            chapter_name: Some(chapter.to_string()),
+            section_name: Some(section.to_string()),
+        };
+        let resources = [
+            resource("Week 1", "Lesson 1"),
+            resource("Week 2", "Lesson 2"),
+            resource("Week 2", "Lesson 3"),
+            resource("Week 3", "Lesson 4"),
+        ];
+        let plan = || {
+            ["notes.pdf", "notes.pdf", "Notes.pdf", "slides.pdf"]
+                .map(|name| (PathBuf::from(name), name.to_string()))
+        };
+        let paths = |plan: &[(PathBuf, String)]| {
+            plan.iter()
+                .map(|(path, _)| path.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut by_chapter = plan();
+        resolve_duplicates(&mut by_chapter, &resources, OnDuplicate::Chapter).unwrap();
+        assert_eq!(
+            paths(&by_chapter),
+            [
+                "1 - notes.pdf",
+                "2 - notes.pdf",
+                "2 - Notes (1).pdf",
+                "slides.pdf"
+            ]
+        );
+
+        let mut by_section = plan();
+        resolve_duplicates(&mut by_section, &resources, OnDuplicate::Section).unwrap();
+        assert_eq!(
+            paths(&by_section),
+            [
+                "Lesson 1 - notes.pdf",
+                "Lesson 2 - notes.pdf",
+                "Lesson 3 - Notes.pdf",
+                "slides.pdf"
+            ]
+        );
+
+        assert!(resolve_duplicates(&mut plan(), &resources, OnDuplicate::Error).is_err());
+    }
+
+    #[test]
+    fn https() {
+        let upgrade = |url| {
+            let mut url = Url::parse(url).unwrap();
+            (upgrade_https(&mut url), url.to_string())
+        };
+        assert_eq!(
+            upgrade("http://nos.netease.com/a.pdf?download=a.pdf"),
+            (
+                true,
+                "https://nos.netease.com/a.pdf?download=a.pdf".to_string()
+            )
+        );
+        assert_eq!(
+            upgrade("http://edu-file.nosdn.127.net/a.pdf"),
+            (true, "https://edu-file.nosdn.127.net/a.pdf".to_string())
+        );
+        assert_eq!(
+            upgrade("http://example.com/a.pdf"),
+            (false, "http://example.com/a.pdf".to_string())
+        );
+        assert_eq!(
+            upgrade("https://nos.netease.com/a.pdf"),
+            (false, "https://nos.netease.com/a.pdf".to_string())
+        );
+    }
+
+    #[test]
+    fn host_rewrites() {
+        let rewrite = |rewrite: &str, url| {
+            let rewrite: HostRewrite = rewrite.parse().unwrap();
+            let mut url = Url::parse(url).unwrap();
+            (rewrite.apply(&mut url), url.to_string())
+        };
+        assert_eq!(
+            rewrite(
+                "nos.netease.com=mirror.example.com",
+                "https://NOS.netease.com/a.pdf?download=a.pdf"
+            ),
+            (
+                true,
+                "https://mirror.example.com/a.pdf?download=a.pdf".to_string()
+            )
+        );
+        assert_eq!(
+            rewrite(
+                "nos.netease.com = http://10.0.0.2:8080",
+                "https://nos.netease.com/a.pdf"
+            ),
+            (true, "http://10.0.0.2:8080/a.pdf".to_string())
+        );
+        assert_eq!(
+            rewrite(
+                "nos.netease.com=mirror.example.com",
+                "https://example.com/a.pdf"
+            ),
+            (false, "https://example.com/a.pdf".to_string())
+        );
+
+        assert!("nos.netease.com".parse::<HostRewrite>().is_err());
+        assert!("nos.netease.com=ftp://mirror"
+            .parse::<HostRewrite>()
+            .is_err());
+        assert!("nos.netease.com=mirror/path"
+            .parse::<HostRewrite>()
+            .is_err());
+        assert!("https://nos.netease.com=mirror"
+            .parse::<HostRewrite>()
+            .is_err());
+    }
+
+    #[test]
+    fn several_files() {
+        let reply = br#"//#DWR-REPLY
+s1.textOrigUrl:"http://nos.netease.com/slides.pdf?download=slides.pdf";
+s2.textOrigUrl:"http://nos.netease.com/reading.pdf?download=reading.pdf";
+s3.textOrigUrl:"http://nos.netease.com/slides.pdf?download=slides.pdf";"#;
+        assert_eq!(
+            file_urls(reply),
+            [
+                "http://nos.netease.com/slides.pdf?download=slides.pdf",
+                "http://nos.netease.com/reading.pdf?download=reading.pdf"
+            ]
+        );
+    }
+
+    #[test]
+    fn content_disposition() {
+        let name = |value: &'static str| content_disposition_name(&HeaderValue::from_static(value));
+        assert_eq!(
+            name(r#"attachment; filename="notes.pdf""#).as_deref(),
+            Some("notes.pdf")
+        );
+        assert_eq!(
+            name("attachment; filename=fallback.pdf; filename*=UTF-8''%E7%AC%AC1%E5%91%A8.pdf")
+                .as_deref(),
+            Some("第1周.pdf")
+        );
+        // Percent-encoded GBK, as some servers send.
+        assert_eq!(
+            name("attachment;filename=%C4%E3%BA%C3.pdf").as_deref(),
+            Some("你好.pdf")
+        );
+        assert_eq!(
+            name(r#"attachment; filename="a \"b\"; c.pdf""#).as_deref(),
+            Some(r#"a "b"; c.pdf"#)
+        );
+        assert_eq!(
+            name(r#"attachment; filename="../../etc/x.pdf""#).as_deref(),
+            Some("x.pdf")
+        );
+        // Decoded with the charset named, falling back to the plain name for unknown ones.
+        assert_eq!(
+            name("attachment; filename*=ISO-8859-1''caf%E9.pdf").as_deref(),
+            Some("café.pdf")
+        );
+        assert_eq!(
+            name("attachment; filename*=GB2312''%C4%E3%BA%C3.pdf").as_deref(),
+            Some("你好.pdf")
+        );
+        assert_eq!(
+            name("attachment; filename=plain.pdf; filename*=x-unknown''%E9.pdf").as_deref(),
+            Some("plain.pdf")
+        );
+        assert_eq!(name("inline"), None);
+        assert_eq!(name(r#"attachment; filename="""#), None);
+
+        assert_eq!(
+            path_with_name(Path::new("第一周/007-a.pdf"), "a.pdf", "第1周.pdf"),
+            Some(PathBuf::from("第一周/007-第1周.pdf"))
+        );
+        assert_eq!(
+            path_with_name(Path::new("a (1).pdf"), "a.pdf", "b.pdf"),
+            None
+        );
+    }
+
+    #[test]
+    fn content_type_extensions() {
+        let pdf = HeaderValue::from_static("application/pdf; charset=binary");
+        assert_eq!(
+            path_for_content_type(Path::new("1.1 绪论"), Some(&pdf)),
+            Some(PathBuf::from("1.1 绪论.pdf"))
+        );
+        assert_eq!(
+            path_for_content_type(Path::new("notes.bin"), Some(&pdf)),
+            Some(PathBuf::from("notes.pdf"))
+        );
+        assert_eq!(
+            path_for_content_type(Path::new("notes.pptx"), Some(&pdf)),
+            None
+        );
+        assert_eq!(path_for_content_type(Path::new("notes"), None), None);
+
+        let unknown = HeaderValue::from_static("application/x-unknown");
+        assert_eq!(
+            path_for_content_type(Path::new("notes"), Some(&unknown)),
+            None
+        );
+    }
+
+    #[test]
+    fn ids_interleaved() {
+        // The section ID of the second unit comes first, and a name mentions another object.
+        let reply = br#"//#DWR-REPLY
+var s0={};var s1={};
+s1.id=4002;s0.name="s1.id=1;";s0.contentId=3001;s1.contentId=3002;s0.id=4001;
+"#;
+        let units = get_ids(&Bytes::from_static(reply));
+
+        let ids: Vec<_> = units
+            .iter()
+            .map(|u| (u.content_id.as_str(), u.section_id.as_str()))
+            .collect();
+        assert_eq!(ids, [("3001", "4001"), ("3002", "4002")]);
+    }
+
+    #[test]
+    fn ids_empty() {
+        let units = get_ids(&Bytes::from_static(b"//#DWR-REPLY\nvar s0={};s0.id=1;"));
+        assert!(units.is_empty());
+    }
+
+    /// Serve `body` with the extra `headers` to every request on a local port, counting the GET
+    /// requests, until the test ends.
+    ///
+    /// If `length` is more than the length of `body`, the connection is held open after it.
+    fn serve(headers: &'static str, body: &'static [u8], length: usize) -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let gets = Arc::new(AtomicUsize::new(0));
+        let counter = gets.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while find(&request, b"\r\n\r\n").is_none() {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {length}\r\nConnection: close\r\n{headers}\r\n"
+                );
+                let _ = stream.write_all(head.as_bytes());
+                if request.starts_with(b"GET ") {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let _ = stream.write_all(body);
+                    if length > body.len() {
+                        let _ = stream.flush();
+                        thread::sleep(Duration::from_secs(60));
+                    }
+                }
+            }
+        });
+        (url, gets)
+    }
+
+    fn options(on_exist: OnExist) -> DownloadOptions {
+        DownloadOptions {
+            concurrency: Arc::new(Concurrency::new(1, None)),
+            attempts: 1,
+            file_retries: 0,
+            force: false,
+            on_exist,
+            on_duplicate: OnDuplicate::Error,
+            number: false,
+            flat: false,
+            filter: FileFilter::default(),
+            min_size: None,
+            max_size: None,
+            progress: ProgressMode::Bar,
+            name_width: None,
+            idle_timeout: None,
+            rate_limiter: None,
+            quota: None,
+            pause: Arc::default(),
+            template: None,
+            cancel: None,
+            zip: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn download_named_by_server() {
+        const BODY: &[u8] = b"%PDF-1.4\n%%EOF\n";
+        let (url, gets) = serve(
+            "Content-Type: application/pdf\r\nContent-Disposition: attachment; filename=\"Week 1.pdf\"\r\n",
+            BODY,
+            BODY.len(),
+        );
+        let dir = std::env::temp_dir().join(format!("mooc-named-{}", std::process::id()));
+        let resources = || {
+            [Resource {
+                url: url.join("file?download=notes.pdf").unwrap(),
+                content_id: None,
+                chapter_name: None,
+                section_name: None,
+            }]
+        };
+        let client = Client::new();
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+
+        for (run, on_exist) in [
+            OnExist::Skip,
+            OnExist::Skip,
+            OnExist::Overwrite,
+            OnExist::Rename,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let options = options(on_exist);
+            let report = download(&client, resources(), &dir, &options, &multi_progress)
+                .await
+                .unwrap();
+            assert_eq!(gets.load(Ordering::SeqCst), 1, "{on_exist:?}");
+            // Only the first run downloads the file, the others find it.
+            assert_eq!(report.existing.len(), run.min(1), "{on_exist:?}");
+            assert_eq!(report.paths, [dir.join("Week 1.pdf")]);
+            // The entry of the file is kept as it is, without reading the file again.
+            let status = &report.manifest.files[0].status;
+            if run == 0 {
+                let mut manifest = report.manifest.clone();
+                manifest.files[0].status = FileStatus::Downloaded {
+                    size: BODY.len() as u64,
+                    sha256: "kept".to_string(),
+                    downloaded_at: 1,
+                };
+                manifest.save(&dir.join(Manifest::FILE_NAME)).await.unwrap();
+            } else {
+                assert!(
+                    matches!(status, FileStatus::Downloaded { sha256, downloaded_at: 1, .. } if sha256 == "kept"),
+                    "{status:?}"
+                );
+            }
+            assert_eq!(
+                existing_files(&resources(), &dir, &options).await.unwrap(),
+                [dir.join("Week 1.pdf")]
+            );
+        }
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["Week 1.pdf", "manifest.json"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_interrupted() {
+        // The server stops sending in the middle of the file, and supports resuming it.
+        let (url, _) = serve("Accept-Ranges: bytes\r\n", b"%PDF-1.4\n", 1000);
+        let dir = std::env::temp_dir().join(format!("mooc-interrupted-{}", std::process::id()));
+        let resources = [Resource {
+            url: url.join("file?download=notes.pdf").unwrap(),
+            content_id: None,
+            chapter_name: None,
+            section_name: None,
+        }];
+        let (cancel_tx, cancel) = watch::channel(false);
+        let options = DownloadOptions {
+            cancel: Some(cancel),
+            ..options(OnExist::Skip)
+        };
+        let client = Client::new();
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+
+        let part_path = dir.join("notes.pdf.part");
+        let interrupt = async {
+            while !part_path.exists() {
+                sleep(Duration::from_millis(10)).await;
+            }
+            cancel_tx.send(true).unwrap();
+        };
+        let (result, ()) = tokio::join!(
+            download(&client, resources, &dir, &options, &multi_progress),
+            interrupt
+        );
+
+        let failed = result.unwrap_err().downcast::<FailedFiles>().unwrap();
+        assert!(format!("{:#}", failed.errors[0]).contains("Interrupted"));
+        assert!(!dir.join("notes.pdf").exists());
+        assert!(!part_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_out_of_range() {
+        const BODY: &[u8] = b"%PDF-1.4\n%%EOF\n";
+        let (url, _) = serve("", BODY, BODY.len());
+        let dir = std::env::temp_dir().join(format!("mooc-range-{}", std::process::id()));
+        let url = url.join("file?download=notes.pdf").unwrap();
+        let resources = [Resource {
+            url: url.clone(),
+            content_id: None,
+            chapter_name: None,
+            section_name: None,
+        }];
+        let options = DownloadOptions {
+            min_size: Some(1000),
+            ..options(OnExist::Skip)
+        };
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+
+        let report = download(&Client::new(), resources, &dir, &options, &multi_progress)
+            .await
+            .unwrap();
+        assert_eq!(report.left_out, [url]);
+        assert!(report.paths.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+use std::io::{self, Write};
+
+use indicatif::MultiProgress;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{fmt::MakeWriter, EnvFilter};
+
+/// Set up logging to stderr, printing above the progress bars of `multi_progress`.
+///
+/// Levels are colored unless colors are disabled for stderr, see [`console::colors_enabled_stderr`].
+///
+/// `level` applies to this crate only. Without it, `RUST_LOG` is respected, and warnings are
+/// logged by default.
+pub fn init(level: Option<LevelFilter>, multi_progress: &MultiProgress) {
+    let filter = match level {
+        Some(level) => EnvFilter::new(format!("mooc_pdf_download={level}")),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(console::colors_enabled_stderr())
+        .with_writer(ProgressWriter(multi_progress.clone()))
+        .init();
+}
+
+/// Writes to stderr while the progress bars are hidden, so they are not garbled by logs.
+#[derive(Clone)]
+struct ProgressWriter(MultiProgress);
+
+impl Write for ProgressWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.suspend(|| io::stderr().write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for ProgressWriter {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
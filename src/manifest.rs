@@ -0,0 +1,221 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::ErrorKind,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tokio::{
+    fs::{read, write, File},
+    io::AsyncReadExt as _,
+};
+
+/// A record of the files downloaded into a directory, saved as `manifest.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+/// A file in the [`Manifest`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The path of the file, relative to the output directory.
+    pub path: String,
+    pub url: String,
+    #[serde(flatten)]
+    pub status: FileStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FileStatus {
+    Downloaded {
+        size: u64,
+        sha256: String,
+        /// Seconds since the Unix epoch.
+        downloaded_at: u64,
+    },
+    Failed {
+        error: String,
+    },
+    /// The file was not downloaded on purpose, like for being too large.
+    Skipped {
+        reason: String,
+    },
+}
+
+impl Manifest {
+    /// The name of the manifest in the directory of a course.
+    pub const FILE_NAME: &str = "manifest.json";
+
+    /// Load the manifest at `path`, or an empty one if there is none yet.
+    pub async fn load(path: &Path) -> eyre::Result<Self> {
+        match read(path).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Keep the entries of `previous` for the files missing from this manifest, like those
+    /// downloaded by earlier runs.
+    pub fn merge(&mut self, previous: Self) {
+        let previous: Vec<_> = previous
+            .files
+            .into_iter()
+            .filter(|old| !self.files.iter().any(|new| new.path == old.path))
+            .collect();
+        self.files.extend(previous);
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    pub async fn save(&self, path: &Path) -> eyre::Result<()> {
+        write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+}
+
+/// How a file on disk compares to its entry in the [`Manifest`], see [`verify_digests`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestCheck {
+    /// The file has the size and SHA-256 digest recorded.
+    Ok,
+    /// The file does not exist.
+    Missing,
+    /// The content of the file changed since it was downloaded.
+    Mismatch,
+}
+
+impl Display for DigestCheck {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "ok",
+            Self::Missing => "missing",
+            Self::Mismatch => "changed since downloaded",
+        })
+    }
+}
+
+/// Check the files downloaded into `dir` against the digests in its manifest, without any
+/// network access.
+///
+/// Returns the path of each file relative to `dir`, as in the manifest. Files which failed or
+/// were skipped are left out.
+pub async fn verify_digests(dir: &Path) -> eyre::Result<Vec<(String, DigestCheck)>> {
+    let content = read(dir.join(Manifest::FILE_NAME)).await?;
+    let manifest: Manifest = serde_json::from_slice(&content)?;
+
+    let mut checks = Vec::new();
+    for entry in manifest.files {
+        let FileStatus::Downloaded { size, sha256, .. } = &entry.status else {
+            continue;
+        };
+        let check = match file_digest(&dir.join(&entry.path)).await {
+            Ok(digest) if digest == (*size, sha256.clone()) => DigestCheck::Ok,
+            Ok(_) => DigestCheck::Mismatch,
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == ErrorKind::NotFound) =>
+            {
+                DigestCheck::Missing
+            }
+            Err(e) => return Err(e.wrap_err(format!("Failed to read {}", entry.path))),
+        };
+        checks.push((entry.path, check));
+    }
+    Ok(checks)
+}
+
+/// Compute the size and SHA-256 digest of a file.
+pub async fn file_digest(path: &Path) -> eyre::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let size = hash_file(path, &mut hasher).await?;
+    Ok((size, hex_digest(hasher)))
+}
+
+/// Feed the content of the file at `path` to `hasher`, returning its size.
+pub(crate) async fn hash_file(path: &Path, hasher: &mut Sha256) -> eyre::Result<u64> {
+    let mut file = File::open(path).await?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok(size)
+}
+
+/// The digest computed by `hasher`, in lowercase hexadecimal.
+pub(crate) fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Format a relative path with `/` separators, regardless of the platform.
+pub fn display_relative(path: &Path) -> String {
+    path.iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_digest, verify_digests, DigestCheck, FileStatus, Manifest, ManifestEntry};
+
+    fn downloaded(path: &str, (size, sha256): (u64, String)) -> ManifestEntry {
+        ManifestEntry {
+            path: path.to_string(),
+            url: format!("https://nos.netease.com/{path}"),
+            status: FileStatus::Downloaded {
+                size,
+                sha256,
+                downloaded_at: 0,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn digests() {
+        let dir = std::env::temp_dir().join(format!("mooc-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.pdf", "b.pdf", "c.pdf"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+
+        let mut previous = Manifest::default();
+        for name in ["a.pdf", "c.pdf"] {
+            let digest = file_digest(&dir.join(name)).await.unwrap();
+            previous.files.push(downloaded(name, digest));
+        }
+        let mut manifest = Manifest::default();
+        let digest = file_digest(&dir.join("b.pdf")).await.unwrap();
+        manifest.files.push(downloaded("b.pdf", digest));
+        manifest.merge(previous);
+        let paths: Vec<_> = manifest.files.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a.pdf", "b.pdf", "c.pdf"]);
+        manifest.save(&dir.join(Manifest::FILE_NAME)).await.unwrap();
+
+        std::fs::write(dir.join("b.pdf"), "changed").unwrap();
+        std::fs::remove_file(dir.join("c.pdf")).unwrap();
+        assert_eq!(
+            verify_digests(&dir).await.unwrap(),
+            [
+                ("a.pdf".to_string(), DigestCheck::Ok),
+                ("b.pdf".to_string(), DigestCheck::Mismatch),
+                ("c.pdf".to_string(), DigestCheck::Missing),
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//! Combining downloaded PDFs into a single one.
+//!
+//! The merged PDF is written to a `.part` file first, and only takes its final name once saved.
+
+use std::{
+    fs::{rename, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use eyre::WrapErr as _;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+
+/// The attributes of pages which may be set on the nodes above them in the page tree instead.
+const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// What [`merge_pdfs`] did.
+#[derive(Debug, Default)]
+pub struct Merged {
+    /// The number of files whose pages were merged.
+    pub files: usize,
+    pub pages: usize,
+    /// The files left out, as they could not be read as PDFs.
+    pub skipped: Vec<(PathBuf, eyre::Report)>,
+}
+
+/// Whether the file at `path` is a PDF, by its extension.
+pub fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"))
+}
+
+/// The page `id` of `doc`, with the attributes it inherits from the page tree set on itself, as
+/// it is moved to another tree.
+fn detached_page(doc: &Document, id: ObjectId) -> eyre::Result<Dictionary> {
+    let mut page = doc.get_dictionary(id)?.clone();
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+    // A malformed tree may loop, so only climb as far as it may be deep.
+    for _ in 0..doc.objects.len() {
+        let Some(node) = parent.and_then(|id| doc.get_dictionary(id).ok()) else {
+            break;
+        };
+        for key in INHERITABLE {
+            if !page.has(key) {
+                if let Ok(value) = node.get(key) {
+                    page.set(key, value.clone());
+                }
+            }
+        }
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    Ok(page)
+}
+
+/// Combine the pages of the PDFs at `paths`, in this order, into a PDF saved at `output`.
+///
+/// Files which cannot be read, like those protected with a password, are left out and listed in
+/// [`Merged::skipped`]. Outlines and forms are not kept. Fails if no page at all was merged.
+///
+/// Blocks, so it should be called from [`tokio::task::spawn_blocking`].
+pub fn merge_pdfs(paths: &[PathBuf], output: &Path) -> eyre::Result<Merged> {
+    let mut merged = Merged::default();
+    let mut document = Document::with_version("1.7");
+    let pages_id = document.new_object_id();
+    let mut kids = Vec::new();
+
+    for path in paths {
+        let mut doc = match Document::load(path) {
+            Ok(doc) => doc,
+            Err(e) => {
+                merged.skipped.push((path.clone(), e.into()));
+                continue;
+            }
+        };
+        doc.renumber_objects_with(document.max_id + 1);
+        let pages: Vec<_> = doc.get_pages().into_values().collect();
+        let detached: eyre::Result<Vec<_>> = pages
+            .iter()
+            .map(|&id| Ok((id, detached_page(&doc, id)?)))
+            .collect();
+        let detached = match detached {
+            Ok(detached) => detached,
+            Err(e) => {
+                merged.skipped.push((path.clone(), e));
+                continue;
+            }
+        };
+        for (id, mut page) in detached {
+            page.set("Parent", pages_id);
+            doc.objects.insert(id, Object::Dictionary(page));
+            kids.push(Object::Reference(id));
+        }
+        // The catalog and page tree of the file are left behind, and pruned below.
+        document.max_id = doc.max_id.max(document.max_id);
+        document.objects.extend(doc.objects);
+        merged.files += 1;
+    }
+
+    if kids.is_empty() {
+        eyre::bail!("No pages to merge");
+    }
+    merged.pages = kids.len();
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+        }),
+    );
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+    document.prune_objects();
+    document.renumber_objects();
+    document.compress();
+
+    let mut part_path = output.as_os_str().to_owned();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+    let file = File::create(&part_path)
+        .wrap_err_with(|| format!("Failed to create {}", part_path.display()))?;
+    let mut writer = BufWriter::new(file);
+    document.save_to(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    rename(&part_path, output)?;
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lopdf::{dictionary, Document, Object, Stream};
+
+    use super::merge_pdfs;
+
+    /// A PDF with `pages` pages, whose media box is only set on the page tree.
+    fn pdf(pages: usize) -> Document {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let kids: Vec<Object> = (0..pages)
+            .map(|_| {
+                let content = doc.add_object(Stream::new(dictionary! {}, b"0 0 m".to_vec()));
+                doc.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "Contents" => content,
+                })
+                .into()
+            })
+            .collect();
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Count" => pages as i64,
+                "Kids" => kids,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog);
+        doc
+    }
+
+    #[test]
+    fn merge() {
+        let dir = std::env::temp_dir().join(format!("mooc-merge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("1.pdf");
+        let second = dir.join("2.pdf");
+        let broken = dir.join("3.pdf");
+        pdf(2).save(&first).unwrap();
+        pdf(3).save(&second).unwrap();
+        fs::write(&broken, "not a PDF").unwrap();
+
+        let output = dir.join("merged.pdf");
+        let merged = merge_pdfs(&[first, broken.clone(), second], &output).unwrap();
+        assert_eq!((merged.files, merged.pages), (2, 5));
+        assert_eq!(merged.skipped.len(), 1);
+        assert_eq!(merged.skipped[0].0, broken);
+
+        // The inherited media box is kept on each page.
+        let doc = Document::load(&output).unwrap();
+        assert_eq!(doc.get_pages().len(), 5);
+        for id in doc.get_pages().into_values() {
+            assert!(doc.get_dictionary(id).unwrap().has(b"MediaBox"));
+        }
+
+        assert!(merge_pdfs(&[broken], &output).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
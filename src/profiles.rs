@@ -0,0 +1,239 @@
+//! Profiles of Chromium-based browsers for `--profile`, including the beta and nightly channels
+//! and the profiles other than the default one, which the extractors of `rookie` do not read.
+
+use std::{
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+/// A browser profile holding cookies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// Like `Chrome Canary (Default)`, or `Opera GX` for browsers with a single profile.
+    pub name: String,
+    /// The cookie database of the profile.
+    pub cookies: PathBuf,
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.cookies.display())
+    }
+}
+
+/// The folders where browsers keep their profiles, by channel.
+#[cfg(all(unix, not(target_os = "macos")))]
+const USER_DATA_DIRS: &[(&str, &str)] = &[
+    ("Chrome", "~/.config/google-chrome"),
+    ("Chrome Beta", "~/.config/google-chrome-beta"),
+    ("Chrome Dev", "~/.config/google-chrome-unstable"),
+    ("Chromium", "~/.config/chromium"),
+    ("Edge", "~/.config/microsoft-edge"),
+    ("Edge Beta", "~/.config/microsoft-edge-beta"),
+    ("Edge Dev", "~/.config/microsoft-edge-dev"),
+    ("Brave", "~/.config/BraveSoftware/Brave-Browser"),
+    ("Vivaldi", "~/.config/vivaldi"),
+    ("Opera", "~/.config/opera"),
+    ("Opera Beta", "~/.config/opera-beta"),
+    ("Opera Developer", "~/.config/opera-developer"),
+];
+
+/// The folders where browsers keep their profiles, by channel.
+#[cfg(target_os = "macos")]
+const USER_DATA_DIRS: &[(&str, &str)] = &[
+    ("Chrome", "~/Library/Application Support/Google/Chrome"),
+    (
+        "Chrome Beta",
+        "~/Library/Application Support/Google/Chrome Beta",
+    ),
+    (
+        "Chrome Dev",
+        "~/Library/Application Support/Google/Chrome Dev",
+    ),
+    (
+        "Chrome Canary",
+        "~/Library/Application Support/Google/Chrome Canary",
+    ),
+    ("Chromium", "~/Library/Application Support/Chromium"),
+    ("Edge", "~/Library/Application Support/Microsoft Edge"),
+    (
+        "Edge Beta",
+        "~/Library/Application Support/Microsoft Edge Beta",
+    ),
+    (
+        "Edge Dev",
+        "~/Library/Application Support/Microsoft Edge Dev",
+    ),
+    (
+        "Edge Canary",
+        "~/Library/Application Support/Microsoft Edge Canary",
+    ),
+    (
+        "Brave",
+        "~/Library/Application Support/BraveSoftware/Brave-Browser",
+    ),
+    ("Vivaldi", "~/Library/Application Support/Vivaldi"),
+    (
+        "Opera",
+        "~/Library/Application Support/com.operasoftware.Opera",
+    ),
+    (
+        "Opera GX",
+        "~/Library/Application Support/com.operasoftware.OperaGX",
+    ),
+];
+
+/// The folders where browsers keep their profiles, by channel.
+#[cfg(windows)]
+const USER_DATA_DIRS: &[(&str, &str)] = &[
+    ("Chrome", "%LOCALAPPDATA%/Google/Chrome/User Data"),
+    ("Chrome Beta", "%LOCALAPPDATA%/Google/Chrome Beta/User Data"),
+    ("Chrome Dev", "%LOCALAPPDATA%/Google/Chrome Dev/User Data"),
+    (
+        "Chrome Canary",
+        "%LOCALAPPDATA%/Google/Chrome SxS/User Data",
+    ),
+    ("Chromium", "%LOCALAPPDATA%/Chromium/User Data"),
+    ("Edge", "%LOCALAPPDATA%/Microsoft/Edge/User Data"),
+    ("Edge Beta", "%LOCALAPPDATA%/Microsoft/Edge Beta/User Data"),
+    ("Edge Dev", "%LOCALAPPDATA%/Microsoft/Edge Dev/User Data"),
+    ("Edge Canary", "%LOCALAPPDATA%/Microsoft/Edge SxS/User Data"),
+    (
+        "Brave",
+        "%LOCALAPPDATA%/BraveSoftware/Brave-Browser/User Data",
+    ),
+    ("Vivaldi", "%LOCALAPPDATA%/Vivaldi/User Data"),
+    ("Opera", "%APPDATA%/Opera Software/Opera Stable"),
+    ("Opera GX", "%APPDATA%/Opera Software/Opera GX Stable"),
+];
+
+/// Replace the `~`, `%LOCALAPPDATA%` or `%APPDATA%` at the start of `path`.
+fn expand(path: &str) -> Option<PathBuf> {
+    let (base, rest) = if let Some(rest) = path.strip_prefix("~/") {
+        (dirs::home_dir()?, rest)
+    } else if let Some(rest) = path.strip_prefix("%LOCALAPPDATA%/") {
+        (dirs::data_local_dir()?, rest)
+    } else if let Some(rest) = path.strip_prefix("%APPDATA%/") {
+        // The roaming folder on Windows.
+        (dirs::config_dir()?, rest)
+    } else {
+        return Some(PathBuf::from(path));
+    };
+    Some(base.join(rest))
+}
+
+/// The cookie database of the profile in the folder `dir`, if any.
+pub fn cookies_in(dir: &Path) -> Option<PathBuf> {
+    ["Network/Cookies", "Cookies", "cookies.sqlite"]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// The profiles with cookies of the browsers installed.
+pub fn discover() -> Vec<Profile> {
+    let dirs: Vec<_> = USER_DATA_DIRS
+        .iter()
+        .filter_map(|(browser, dir)| Some((*browser, expand(dir)?)))
+        .collect();
+    find(&dirs)
+}
+
+/// The profiles in each of the folders `dirs` of browsers, which is either a profile itself, like
+/// for Opera, or holds a `Default` profile and others named like `Profile 1`.
+fn find(dirs: &[(&str, PathBuf)]) -> Vec<Profile> {
+    let mut profiles = Vec::new();
+    for (browser, dir) in dirs {
+        if let Some(cookies) = cookies_in(dir) {
+            profiles.push(Profile {
+                name: browser.to_string(),
+                cookies,
+            });
+            continue;
+        }
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut found: Vec<_> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let profile = entry.file_name().to_string_lossy().into_owned();
+                if profile != "Default" && !profile.starts_with("Profile ") {
+                    return None;
+                }
+                Some(Profile {
+                    name: format!("{browser} ({profile})"),
+                    cookies: cookies_in(&entry.path())?,
+                })
+            })
+            .collect();
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        profiles.extend(found);
+    }
+    profiles
+}
+
+/// The profiles among `profiles` named `name`, like `Chrome (Profile 1)`, or all those of a
+/// browser, like `Chrome` for `Chrome (Default)` and `Chrome (Profile 1)` but not `Chrome Beta`.
+pub fn matching(name: &str, profiles: &[Profile]) -> Vec<Profile> {
+    let name = name.trim().to_lowercase();
+    profiles
+        .iter()
+        .filter(|profile| {
+            let profile = profile.name.to_lowercase();
+            profile == name || profile.starts_with(&format!("{name} ("))
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, matching};
+
+    #[test]
+    fn profiles() {
+        let root = std::env::temp_dir().join(format!("mooc-profiles-{}", std::process::id()));
+        let chrome = root.join("google-chrome");
+        let canary = root.join("chrome-canary");
+        let opera = root.join("opera-gx");
+        for dir in [
+            chrome.join("Default/Network"),
+            chrome.join("Profile 1"),
+            chrome.join("System Profile"),
+            canary.join("Default"),
+            opera.clone(),
+        ] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        for file in [
+            chrome.join("Default/Network/Cookies"),
+            chrome.join("Profile 1/Cookies"),
+            chrome.join("System Profile/Cookies"),
+            opera.join("Cookies"),
+        ] {
+            std::fs::write(file, "").unwrap();
+        }
+
+        let profiles = find(&[
+            ("Chrome", chrome.clone()),
+            ("Chrome Canary", canary),
+            ("Opera GX", opera.clone()),
+            ("Edge", root.join("missing")),
+        ]);
+        let names: Vec<_> = profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["Chrome (Default)", "Chrome (Profile 1)", "Opera GX"]
+        );
+        assert_eq!(profiles[0].cookies, chrome.join("Default/Network/Cookies"));
+        assert_eq!(profiles[2].cookies, opera.join("Cookies"));
+
+        assert_eq!(matching("chrome", &profiles).len(), 2);
+        assert_eq!(matching("Chrome (Profile 1)", &profiles).len(), 1);
+        assert_eq!(matching("Opera GX", &profiles).len(), 1);
+        assert!(matching("Opera", &profiles).is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
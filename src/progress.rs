@@ -0,0 +1,228 @@
+//! How the progress of downloads is shown, see [`ProgressMode`].
+
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use unicode_width::{UnicodeWidthChar as _, UnicodeWidthStr as _};
+
+/// How the progress of downloads is shown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProgressMode {
+    /// Progress bars, with the speed and the time left of each file.
+    #[default]
+    Bar,
+    /// A line for each file once done, without redrawing anything.
+    Plain,
+    /// Newline-delimited JSON events on stdout, for other programs to follow, see [`Event`].
+    Json,
+}
+
+/// What happened to a file, printed as a line of JSON with [`ProgressMode::Json`].
+///
+/// Files are told apart by their URLs, as their paths may change while downloading.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Started {
+        url: &'a str,
+        file_name: &'a str,
+        size: Option<u64>,
+    },
+    Progress {
+        url: &'a str,
+        downloaded: u64,
+        size: Option<u64>,
+    },
+    Finished {
+        url: &'a str,
+        /// The path of the file, relative to the output directory.
+        path: &'a str,
+        size: u64,
+    },
+    Skipped {
+        url: &'a str,
+    },
+    Failed {
+        url: &'a str,
+        error: String,
+    },
+}
+
+impl Event<'_> {
+    pub fn emit(&self) {
+        if let Ok(line) = serde_json::to_string(self) {
+            println!("{line}");
+        }
+    }
+}
+
+/// Cut `name` to `width` columns, ending it with `…` if cut, and pad it with spaces to exactly
+/// `width` columns so that the bars after it line up.
+///
+/// Columns are counted as in terminals, where most Chinese characters take two of them.
+pub fn fit_width(name: &str, width: usize) -> String {
+    let name_width = name.width();
+    if name_width <= width {
+        return format!("{name}{}", " ".repeat(width - name_width));
+    }
+    let mut fitted = String::new();
+    let mut used = 0;
+    // Keep a column for the ellipsis.
+    for c in name.chars() {
+        let c_width = c.width().unwrap_or(0);
+        if used + c_width + 1 > width {
+            break;
+        }
+        fitted.push(c);
+        used += c_width;
+    }
+    if width > 0 {
+        fitted.push('…');
+        used += 1;
+    }
+    // A wide character may leave a column free.
+    fitted + &" ".repeat(width - used)
+}
+
+/// The progress of a single file, shown as told by a [`ProgressMode`].
+pub(crate) enum FileProgress {
+    Bar(ProgressBar),
+    Json {
+        url: String,
+        downloaded: u64,
+        size: Option<u64>,
+        last_emitted: Instant,
+    },
+    Hidden,
+}
+
+impl FileProgress {
+    /// How often [`Event::Progress`] is emitted for a file.
+    const JSON_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// Start showing the progress of `file_name` downloaded from `url`, with `downloaded` out of
+    /// `size` bytes already done.
+    ///
+    /// The name is fitted to `name_width` columns in progress bars, if given, see [`fit_width`].
+    pub(crate) fn new(
+        mode: ProgressMode,
+        multi_progress: &MultiProgress,
+        url: &str,
+        file_name: &str,
+        name_width: Option<usize>,
+        downloaded: u64,
+        size: Option<u64>,
+    ) -> Self {
+        let prefix = match name_width {
+            Some(width) => fit_width(file_name, width),
+            None => file_name.to_string(),
+        };
+        match (mode, size) {
+            (ProgressMode::Bar, Some(size)) => Self::Bar(
+                multi_progress.add(
+                    ProgressBar::new(size)
+                        .with_position(downloaded)
+                        .with_prefix(prefix)
+                        .with_style(
+                            ProgressStyle::with_template(
+                                "{prefix} {wide_bar} {binary_bytes}/{binary_total_bytes} \
+                                 {binary_bytes_per_sec} {eta}",
+                            )
+                            .unwrap(),
+                        ),
+                ),
+            ),
+            // Without a size, at least show that bytes keep coming.
+            (ProgressMode::Bar, None) => {
+                let spinner = multi_progress.add(
+                    ProgressBar::new_spinner()
+                        .with_position(downloaded)
+                        .with_prefix(prefix)
+                        .with_style(
+                            ProgressStyle::with_template(
+                                "{prefix} {spinner} {binary_bytes} {binary_bytes_per_sec}",
+                            )
+                            .unwrap(),
+                        ),
+                );
+                spinner.enable_steady_tick(Duration::from_millis(100));
+                Self::Bar(spinner)
+            }
+            (ProgressMode::Json, _) => {
+                Event::Started {
+                    url,
+                    file_name,
+                    size,
+                }
+                .emit();
+                Self::Json {
+                    url: url.to_string(),
+                    downloaded,
+                    size,
+                    last_emitted: Instant::now(),
+                }
+            }
+            _ => Self::Hidden,
+        }
+    }
+
+    pub(crate) fn inc(&mut self, len: u64) {
+        match self {
+            Self::Bar(pb) => pb.inc(len),
+            Self::Json {
+                url,
+                downloaded,
+                size,
+                last_emitted,
+            } => {
+                *downloaded += len;
+                if last_emitted.elapsed() >= Self::JSON_INTERVAL {
+                    *last_emitted = Instant::now();
+                    Event::Progress {
+                        url,
+                        downloaded: *downloaded,
+                        size: *size,
+                    }
+                    .emit();
+                }
+            }
+            Self::Hidden => {}
+        }
+    }
+
+    /// Stop showing the progress, as the overall progress is shown by [`download`](crate::download).
+    pub(crate) fn finish(self) {
+        if let Self::Bar(pb) = self {
+            pb.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fit_width, Event};
+
+    #[test]
+    fn widths() {
+        assert_eq!(fit_width("a.pdf", 8), "a.pdf   ");
+        assert_eq!(fit_width("第一周.pdf", 10), "第一周.pdf");
+        assert_eq!(fit_width("第一周课件.pdf", 10), "第一周课… ");
+        assert_eq!(fit_width("lecture-01.pdf", 8), "lecture…");
+        assert_eq!(fit_width("a.pdf", 0), "");
+    }
+
+    #[test]
+    fn events() {
+        let event = Event::Finished {
+            url: "https://nos.netease.com/a.pdf",
+            path: "第一周/a.pdf",
+            size: 1024,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"finished","url":"https://nos.netease.com/a.pdf","path":"第一周/a.pdf","size":1024}"#
+        );
+    }
+}
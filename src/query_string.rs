@@ -1,22 +1,56 @@
 use std::string::FromUtf8Error;
 
+use encoding_rs::GBK;
+
 /// Replaces %xx escapes by their single-character equivalent.
 ///
 /// # Examples
 ///
 /// ```
-/// use urlparse::unquote;
+/// use mooc_pdf_download::query_string::unquote;
 ///
 /// let s = unquote("ABC%3D123%21%20DEF%3D%23%23");
 /// assert_eq!(s.ok().unwrap(), "ABC=123! DEF=##");
 /// ```
 ///
 pub fn unquote<S: AsRef<[u8]>>(s: S) -> Result<String, FromUtf8Error> {
+    String::from_utf8(unquote_to_bytes(s))
+}
+
+/// Like unquote(), but never fails on the decoded bytes.
+///
+/// Bytes that are not valid UTF-8 are decoded as GBK, which some older file names are encoded
+/// in, and as UTF-8 with replacement characters if they are not valid GBK either.
+///
+/// # Examples
+///
+/// ```
+/// use mooc_pdf_download::query_string::unquote_lossy;
+///
+/// assert_eq!(unquote_lossy("%E4%BD%A0%E5%A5%BD.pdf"), "你好.pdf");
+/// assert_eq!(unquote_lossy("%C4%E3%BA%C3.pdf"), "你好.pdf");
+/// ```
+///
+pub fn unquote_lossy<S: AsRef<[u8]>>(s: S) -> String {
+    decode_lossy(unquote_to_bytes(s))
+}
+
+/// Like unquote(), but returns the decoded bytes as they are.
+///
+/// # Examples
+///
+/// ```
+/// use mooc_pdf_download::query_string::unquote_to_bytes;
+///
+/// assert_eq!(unquote_to_bytes("%C4%E3%25"), b"\xC4\xE3%");
+/// ```
+///
+pub fn unquote_to_bytes<S: AsRef<[u8]>>(s: S) -> Vec<u8> {
     let mut result: Vec<u8> = Vec::new();
     let mut items = s.as_ref().split(|&b| b == b'%');
     match items.next() {
         Some(item) => result.append(&mut item.to_vec()),
-        None => return String::from_utf8(result),
+        None => return result,
     }
     for item in items {
         match item.len() {
@@ -38,7 +72,7 @@ pub fn unquote<S: AsRef<[u8]>>(s: S) -> Result<String, FromUtf8Error> {
             }
         }
     }
-    String::from_utf8(result)
+    result
 }
 
 /// Like unquote(), but also replaces plus signs by spaces, as required for
@@ -47,22 +81,139 @@ pub fn unquote<S: AsRef<[u8]>>(s: S) -> Result<String, FromUtf8Error> {
 /// # Examples
 ///
 /// ```
-/// use urlparse::unquote_plus;
+/// use mooc_pdf_download::query_string::unquote_plus;
 ///
 /// let s = unquote_plus("ABC%3D123%21+DEF%3D%23%23");
 /// assert_eq!(s.ok().unwrap(), "ABC=123! DEF=##");
 /// ```
 ///
 pub fn unquote_plus<S: AsRef<[u8]>>(s: S) -> Result<String, FromUtf8Error> {
-    let s: Vec<u8> = s
-        .as_ref()
+    unquote(plus_to_space(s))
+}
+
+/// Like unquote_lossy(), but also replaces plus signs by spaces, as required for
+/// unquoting HTML form values.
+///
+/// # Examples
+///
+/// ```
+/// use mooc_pdf_download::query_string::unquote_plus_lossy;
+///
+/// assert_eq!(unquote_plus_lossy("%C4%E3+%BA%C3"), "你 好");
+/// ```
+///
+pub fn unquote_plus_lossy<S: AsRef<[u8]>>(s: S) -> String {
+    unquote_lossy(plus_to_space(s))
+}
+
+fn plus_to_space<S: AsRef<[u8]>>(s: S) -> Vec<u8> {
+    s.as_ref()
         .iter()
         .map(|&b| match b {
             b'+' => b' ',
             _ => b,
         })
-        .collect();
-    unquote(s)
+        .collect()
+}
+
+fn decode_lossy(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => {
+            let bytes = e.into_bytes();
+            match GBK.decode_without_bom_handling_and_without_replacement(&bytes) {
+                Some(s) => s.into_owned(),
+                None => String::from_utf8_lossy(&bytes).into_owned(),
+            }
+        }
+    }
+}
+
+/// Parses a query string like `a=1&b=2` into its decoded key-value pairs, in order.
+///
+/// Keys and values are decoded with unquote_plus_lossy(). Empty segments are skipped, and a key
+/// without `=` gets an empty value.
+///
+/// # Examples
+///
+/// ```
+/// use mooc_pdf_download::query_string::parse_query_string;
+///
+/// let pairs = parse_query_string("download=%E7%AC%AC1%E7%AB%A0.pdf&&flag&a+b=c%3Dd");
+/// assert_eq!(
+///     pairs,
+///     [
+///         ("download".to_string(), "第1章.pdf".to_string()),
+///         ("flag".to_string(), String::new()),
+///         ("a b".to_string(), "c=d".to_string()),
+///     ]
+/// );
+/// ```
+///
+pub fn parse_query_string(s: &str) -> Vec<(String, String)> {
+    s.split('&')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            let (key, value) = segment.split_once('=').unwrap_or((segment, ""));
+            (unquote_plus_lossy(key), unquote_plus_lossy(value))
+        })
+        .collect()
+}
+
+/// Replaces special characters in string using the %xx escape.
+///
+/// Letters, digits, and the characters `-_.~` are never quoted.
+///
+/// # Examples
+///
+/// ```
+/// use mooc_pdf_download::query_string::quote;
+///
+/// let s = quote("ABC=123! DEF=##");
+/// assert_eq!(s, "ABC%3D123%21%20DEF%3D%23%23");
+/// ```
+///
+pub fn quote<S: AsRef<[u8]>>(s: S) -> String {
+    let mut result = String::new();
+    for &b in s.as_ref() {
+        push_quoted(&mut result, b);
+    }
+    result
+}
+
+/// Like quote(), but also replaces spaces by plus signs, as required for
+/// quoting HTML form values.
+///
+/// # Examples
+///
+/// ```
+/// use mooc_pdf_download::query_string::quote_plus;
+///
+/// let s = quote_plus("ABC=123! DEF=##");
+/// assert_eq!(s, "ABC%3D123%21+DEF%3D%23%23");
+/// ```
+///
+pub fn quote_plus<S: AsRef<[u8]>>(s: S) -> String {
+    let mut result = String::new();
+    for &b in s.as_ref() {
+        match b {
+            b' ' => result.push('+'),
+            _ => push_quoted(&mut result, b),
+        }
+    }
+    result
+}
+
+fn push_quoted(result: &mut String, b: u8) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+        result.push(b as char);
+    } else {
+        result.push('%');
+        result.push(HEX[(b >> 4) as usize] as char);
+        result.push(HEX[(b & 0xF) as usize] as char);
+    }
 }
 
 fn to_digit(n1: u8, n2: u8) -> Option<u8> {
@@ -80,3 +231,43 @@ fn hex_char_to_dec(n: u8) -> Option<u8> {
         None?
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rng, RngExt as _};
+
+    use super::{quote, quote_plus, unquote, unquote_plus, unquote_to_bytes};
+
+    #[test]
+    fn unquote_edge_cases() {
+        let cases = [
+            ("", ""),
+            ("%", "%"),
+            ("abc%", "abc%"),
+            ("%4", "%4"),
+            ("%4a", "J"),
+            ("%GG", "%GG"),
+            ("%%41", "%A"),
+            ("%41%", "A%"),
+            ("100%25", "100%"),
+        ];
+        for (quoted, expected) in cases {
+            assert_eq!(unquote(quoted).unwrap(), expected, "unquoting {quoted:?}");
+        }
+        assert!(unquote("%FF").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = rng();
+        for _ in 0..1000 {
+            let len = rng.random_range(0..32);
+            let bytes: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+
+            assert_eq!(unquote_to_bytes(quote(&bytes)), bytes);
+            let s = String::from_utf8_lossy(&bytes);
+            assert_eq!(unquote(quote(s.as_bytes())).unwrap(), s);
+            assert_eq!(unquote_plus(quote_plus(s.as_bytes())).unwrap(), s);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::{future::Future, time::Duration};
+
+use rand::{rng, RngExt as _};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use tokio::time::sleep;
+
+/// The delay before the first retry, doubled for each following one.
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// The upper bound of the delay between two attempts, before jitter.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Call `f` up to `attempts` times, until it succeeds or fails with an error not worth retrying.
+///
+/// Network errors and 5xx responses are retried with exponential backoff and jitter, while 4xx
+/// responses are returned immediately, as they usually mean the session is invalid.
+pub async fn with_retry<T, F, Fut>(attempts: u32, mut f: F) -> reqwest::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = reqwest::Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(e) if attempt < attempts && is_transient(&e) => {
+                sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => status.is_server_error(),
+        None => error.is_timeout() || error.is_connect() || error.is_request() || error.is_body(),
+    }
+}
+
+/// The delay to wait after the failed `attempt`, counting from 1.
+pub fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_DELAY);
+    delay + delay.mul_f64(rng().random_range(0.0..0.5))
+}
+
+/// The delay asked for by the `Retry-After` header, if given in seconds.
+///
+/// The HTTP-date form is not supported, and treated as if the header were missing.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+    use super::retry_after;
+
+    #[test]
+    fn retry_after_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+}
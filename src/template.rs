@@ -0,0 +1,153 @@
+//! Templates for the paths of downloaded files, like `{chapter}/{index}-{filename}`.
+
+use std::{path::PathBuf, str::FromStr};
+
+use crate::sanitize_file_name;
+
+/// A value that can be put into a path by an [`OutputTemplate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// The tid of the course.
+    Tid,
+    /// The name of the course.
+    Course,
+    /// The name of the chapter, usually a week.
+    Chapter,
+    /// The name of the section, or lesson.
+    Section,
+    /// The name of the file, including its extension.
+    FileName,
+    /// The position of the file in the course, starting from 1 and zero-padded.
+    Index,
+}
+
+impl Placeholder {
+    const NAMES: [(&str, Self); 6] = [
+        ("tid", Self::Tid),
+        ("course", Self::Course),
+        ("chapter", Self::Chapter),
+        ("section", Self::Section),
+        ("filename", Self::FileName),
+        ("index", Self::Index),
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// The path of a file relative to the output directory, with `/` separating folders and
+/// placeholders like `{filename}` in braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate(Vec<Part>);
+
+impl OutputTemplate {
+    /// Replace `placeholder` with `value` wherever it appears, leaving the others as they are.
+    pub fn fill(mut self, placeholder: Placeholder, value: Option<&str>) -> Self {
+        for part in &mut self.0 {
+            if *part == Part::Placeholder(placeholder) {
+                *part = Part::Literal(value.map(sanitize_file_name).unwrap_or_default());
+            }
+        }
+        self
+    }
+
+    /// Build a path, getting the values of the placeholders from `value`.
+    ///
+    /// Values are sanitized to be valid file names, and folders left empty because of a missing
+    /// value are skipped.
+    pub fn expand(&self, value: impl Fn(Placeholder) -> Option<String>) -> PathBuf {
+        let path: String = self
+            .0
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Placeholder(placeholder) => value(*placeholder)
+                    .map(|value| sanitize_file_name(&value))
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        path.split('/')
+            .filter(|component| !matches!(*component, "" | "." | ".."))
+            .collect()
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| eyre::eyre!("Unclosed `{{` in the output template"))?;
+            let name = &rest[start + 1..start + end];
+
+            let placeholder = Placeholder::NAMES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, placeholder)| *placeholder)
+                .ok_or_else(|| {
+                    let names = Placeholder::NAMES
+                        .iter()
+                        .map(|(n, _)| format!("{{{n}}}"))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    eyre::eyre!("Unknown placeholder `{{{name}}}`, expected one of {names}")
+                })?;
+            parts.push(Part::Placeholder(placeholder));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+
+        if !parts.contains(&Part::Placeholder(Placeholder::FileName))
+            && !parts.contains(&Part::Placeholder(Placeholder::Index))
+        {
+            eyre::bail!("The output template must contain {{filename}} or {{index}}");
+        }
+
+        Ok(Self(parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{OutputTemplate, Placeholder};
+
+    #[test]
+    fn expand() {
+        let template: OutputTemplate = "{course}/{chapter}/{index}-{filename}".parse().unwrap();
+        let template = template.fill(Placeholder::Course, Some("高等数学: 上"));
+
+        let path = template.expand(|placeholder| match placeholder {
+            Placeholder::FileName => Some("1.1 绪论.pdf".to_string()),
+            Placeholder::Index => Some("01".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            path,
+            PathBuf::from_iter(["高等数学_ 上", "01-1.1 绪论.pdf"])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!("{chapter}/{name}".parse::<OutputTemplate>().is_err());
+        assert!("{chapter/{filename}".parse::<OutputTemplate>().is_err());
+        assert!("{chapter}/notes.pdf".parse::<OutputTemplate>().is_err());
+        assert!("{tid}-{index}.pdf".parse::<OutputTemplate>().is_ok());
+    }
+}
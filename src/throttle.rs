@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rand::{rng, RngExt as _};
+use reqwest::Url;
+use tokio::{
+    sync::{AcquireError, OwnedSemaphorePermit, Semaphore},
+    time::{sleep, sleep_until},
+};
+
+/// Bounds the requests in flight, in total and to each host, so that a high total does not hammer
+/// a single server.
+#[derive(Debug)]
+pub struct Concurrency {
+    total: Arc<Semaphore>,
+    per_host: Option<usize>,
+    /// Created on demand, the first time a host is requested.
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// Held while a request is in flight, see [`Concurrency::acquire`].
+#[derive(Debug)]
+pub struct Permit {
+    _host: Option<OwnedSemaphorePermit>,
+    _total: OwnedSemaphorePermit,
+}
+
+impl Concurrency {
+    /// Allow `total` requests in flight, and at most `per_host` of them to the same host.
+    pub fn new(total: usize, per_host: Option<usize>) -> Self {
+        Self {
+            total: Arc::new(Semaphore::new(total)),
+            per_host,
+            hosts: Mutex::default(),
+        }
+    }
+
+    /// Wait for the turn of a request to `url`.
+    ///
+    /// The permit of the host is taken first, so that requests waiting for a busy host do not
+    /// hold back those to other hosts.
+    pub async fn acquire(&self, url: &Url) -> Result<Permit, AcquireError> {
+        let host = match (self.per_host, url.host_str()) {
+            (Some(per_host), Some(host)) => {
+                let semaphore = self
+                    .hosts
+                    .lock()
+                    .unwrap()
+                    .entry(host.to_ascii_lowercase())
+                    .or_insert_with(|| Arc::new(Semaphore::new(per_host)))
+                    .clone();
+                Some(semaphore.acquire_owned().await?)
+            }
+            _ => None,
+        };
+        Ok(Permit {
+            _host: host,
+            _total: self.total.clone().acquire_owned().await?,
+        })
+    }
+}
+
+/// A token bucket limiting the total throughput of all downloads sharing it.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// The allowed throughput, in bytes per second.
+    rate: u64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// The number of bytes which may be consumed right away, negative if already overdrawn.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Consume `bytes` from the bucket, waiting until the throughput is back under the limit.
+    ///
+    /// The bucket holds at most one second worth of bytes, so bursts stay short.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.rate as f64;
+            bucket.tokens = (bucket.tokens + refill).min(self.rate as f64) - bytes as f64;
+            bucket.last_refill = now;
+
+            (bucket.tokens < 0.0)
+                .then(|| Duration::from_secs_f64(-bucket.tokens / self.rate as f64))
+        };
+
+        if let Some(wait) = wait {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// Pauses all the downloads sharing it, after the server asked to slow down with HTTP 429.
+#[derive(Debug, Default)]
+pub struct Pause {
+    until: Mutex<Option<Instant>>,
+}
+
+impl Pause {
+    /// Pause for `duration` from now, unless already paused for longer.
+    pub fn pause(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut current = self.until.lock().unwrap();
+        if current.is_none_or(|current| current < until) {
+            *current = Some(until);
+        }
+    }
+
+    /// Wait until the pause is over, returning right away if not paused.
+    pub async fn wait(&self) {
+        loop {
+            // The pause may be extended while waiting, so check again afterwards.
+            let until = *self.until.lock().unwrap();
+            match until.map(|until| until.saturating_duration_since(Instant::now())) {
+                Some(wait) if !wait.is_zero() => sleep(wait).await,
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Spaces out requests sharing it by a delay with random jitter, so that they do not look like a
+/// burst from a bot.
+#[derive(Debug)]
+pub struct Pacer {
+    delay: Duration,
+    /// When the next request may be sent.
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    /// Space out requests by `delay` on average, each delay being between half and one and a half
+    /// times as long.
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for the turn of a request, and push back the one after it.
+    pub async fn wait(&self) {
+        let turn = {
+            let mut next = self.next.lock().unwrap();
+            let turn = (*next).max(Instant::now());
+            *next = turn + self.delay.mul_f64(rng().random_range(0.5..1.5));
+            turn
+        };
+        sleep_until(turn.into()).await;
+    }
+}
+
+/// Caps the total number of bytes downloaded by all the downloads sharing it, like for a metered
+/// connection.
+///
+/// Once some bytes do not fit, the quota is reached for good: no other download is started, and
+/// those in progress are stopped, instead of trying to fill what is left with smaller files.
+#[derive(Debug)]
+pub struct Quota {
+    limit: u64,
+    used: AtomicU64,
+    /// Set once some bytes did not fit.
+    reached: AtomicBool,
+}
+
+impl Quota {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+            reached: AtomicBool::new(false),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The number of bytes which may still be downloaded.
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used.load(Ordering::Relaxed))
+    }
+
+    pub fn is_reached(&self) -> bool {
+        self.reached.load(Ordering::Relaxed)
+    }
+
+    /// Fail with [`QuotaReached`] if `bytes` more would not fit, without counting them.
+    pub fn check(&self, bytes: u64) -> Result<(), QuotaReached> {
+        if self.is_reached() || bytes > self.remaining() {
+            self.reached.store(true, Ordering::Relaxed);
+            return Err(QuotaReached);
+        }
+        Ok(())
+    }
+
+    /// Count `bytes` as downloaded, or fail with [`QuotaReached`] if they do not fit, in which
+    /// case they are not counted.
+    pub fn consume(&self, bytes: u64) -> Result<(), QuotaReached> {
+        if self.is_reached() {
+            return Err(QuotaReached);
+        }
+        let result = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&used| used <= self.limit)
+            });
+        if result.is_err() {
+            self.reached.store(true, Ordering::Relaxed);
+            return Err(QuotaReached);
+        }
+        Ok(())
+    }
+}
+
+/// The error of a download stopped, or not started, as it would not fit in the [`Quota`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaReached;
+
+impl Display for QuotaReached {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The quota of downloaded bytes was reached")
+    }
+}
+
+impl std::error::Error for QuotaReached {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use reqwest::Url;
+
+    use super::{Concurrency, Pacer, Quota, QuotaReached};
+
+    #[tokio::test]
+    async fn concurrency() {
+        let concurrency = Concurrency::new(3, Some(2));
+        let a = Url::parse("https://a.example.com/1.pdf").unwrap();
+        let b = Url::parse("https://B.example.com/2.pdf").unwrap();
+        let _a1 = concurrency.acquire(&a).await.unwrap();
+        let _a2 = concurrency.acquire(&a).await.unwrap();
+        let b1 = concurrency.acquire(&b).await.unwrap();
+
+        // The host is busy, then so are all the requests.
+        let wait = Duration::from_millis(20);
+        assert!(tokio::time::timeout(wait, concurrency.acquire(&a))
+            .await
+            .is_err());
+        assert!(tokio::time::timeout(wait, concurrency.acquire(&b))
+            .await
+            .is_err());
+        drop(b1);
+        let b = Url::parse("https://b.example.com/3.pdf").unwrap();
+        assert!(tokio::time::timeout(wait, concurrency.acquire(&b))
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn quota() {
+        let quota = Quota::new(100);
+        assert_eq!(quota.check(100), Ok(()));
+        assert_eq!(quota.consume(60), Ok(()));
+        assert_eq!(quota.remaining(), 40);
+        assert!(!quota.is_reached());
+
+        // The bytes refused are not counted, and nothing fits anymore.
+        assert_eq!(quota.consume(60), Err(QuotaReached));
+        assert!(quota.is_reached());
+        assert_eq!(quota.remaining(), 40);
+        assert_eq!(quota.consume(10), Err(QuotaReached));
+        assert_eq!(quota.check(0), Err(QuotaReached));
+    }
+
+    #[tokio::test]
+    async fn pacing() {
+        let pacer = Pacer::new(Duration::from_millis(20));
+        let start = Instant::now();
+        for _ in 0..3 {
+            pacer.wait().await;
+        }
+        // The first request goes right away, and each of the others waits at least half the delay.
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
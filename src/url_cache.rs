@@ -0,0 +1,175 @@
+//! A cache of the file URLs found by [`get_pdf_urls`](crate::get_pdf_urls), which takes a request
+//! for each unit of a course.
+//!
+//! The URLs are signed and stop working after a while, so the cache expires after
+//! [`UrlCache::TTL`].
+
+use std::{
+    io::ErrorKind,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tokio::fs::{create_dir_all, read, rename, write};
+
+use crate::{Resource, Unit};
+
+/// The file URLs of a course, saved as JSON.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UrlCache {
+    /// Tells apart the session, units and extensions the URLs were found for, see [`Self::key`].
+    key: String,
+    /// When the URLs should be fetched again, in seconds since the Unix epoch.
+    expires_at: u64,
+    resources: Vec<CachedResource>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResource {
+    url: String,
+    #[serde(default)]
+    content_id: Option<String>,
+    chapter_name: Option<String>,
+    section_name: Option<String>,
+}
+
+impl UrlCache {
+    /// How long the cached URLs are used, as the signed ones stop working after a while.
+    pub const TTL: Duration = Duration::from_secs(60 * 60);
+
+    /// A digest of the `units` and `extensions` the URLs are found for, and of the ID of the
+    /// session they are signed for, so that choosing other chapters or extensions, or logging in
+    /// again or as someone else, does not reuse the URLs of the previous ones.
+    pub fn key(session_id: &str, units: &[Unit], extensions: &[String]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(session_id);
+        hasher.update([0]);
+        for unit in units {
+            for field in [
+                Some(&unit.content_id),
+                Some(&unit.section_id),
+                unit.chapter_name.as_ref(),
+                unit.section_name.as_ref(),
+            ] {
+                hasher.update(field.map_or("", String::as_str));
+                hasher.update([0]);
+            }
+        }
+        for extension in extensions {
+            hasher.update(extension.to_lowercase());
+            hasher.update([0]);
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Cache `resources` found for `key` until [`Self::TTL`] after `now`.
+    pub fn new(key: String, resources: &[Resource], now: SystemTime) -> Self {
+        let expires_at = (now + Self::TTL)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Self {
+            key,
+            expires_at,
+            resources: resources
+                .iter()
+                .map(|resource| CachedResource {
+                    url: resource.url.to_string(),
+                    content_id: resource.content_id.clone(),
+                    chapter_name: resource.chapter_name.clone(),
+                    section_name: resource.section_name.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The cached resources, if they were found for `key` and have not expired at `now`.
+    pub fn resources(&self, key: &str, now: SystemTime) -> Option<Vec<Resource>> {
+        let now = now.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_secs();
+        if self.key != key || now >= self.expires_at {
+            return None;
+        }
+        self.resources
+            .iter()
+            .map(|resource| {
+                Some(Resource {
+                    url: Url::parse(&resource.url).ok()?,
+                    content_id: resource.content_id.clone(),
+                    chapter_name: resource.chapter_name.clone(),
+                    section_name: resource.section_name.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Load the cache at `path`, or `None` if there is none yet.
+    pub async fn load(path: &Path) -> eyre::Result<Option<Self>> {
+        match read(path).await {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the cache to `path`, replacing the previous one only once fully written.
+    pub async fn save(&self, path: &Path) -> eyre::Result<()> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).await?;
+        }
+        let mut part_path = path.as_os_str().to_owned();
+        part_path.push(".part");
+        write(&part_path, serde_json::to_vec(self)?).await?;
+        rename(&part_path, path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use reqwest::Url;
+
+    use super::UrlCache;
+    use crate::{Resource, Unit};
+
+    #[test]
+    fn expiry() {
+        let units = [Unit {
+            content_id: "1".to_string(),
+            section_id: "2".to_string(),
+            chapter_name: Some("第一周".to_string()),
+            section_name: None,
+        }];
+        let pdf = UrlCache::key("abc", &units, &["pdf".to_string()]);
+        let pptx = UrlCache::key("abc", &units, &["pptx".to_string()]);
+        assert_ne!(pdf, pptx);
+        assert_eq!(pdf, UrlCache::key("abc", &units, &["PDF".to_string()]));
+        assert_ne!(pdf, UrlCache::key("def", &units, &["pdf".to_string()]));
+
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let resources = [Resource {
+            url: Url::parse("https://nos.netease.com/a.pdf?Signature=x").unwrap(),
+            content_id: Some("1".to_string()),
+            chapter_name: Some("第一周".to_string()),
+            section_name: None,
+        }];
+        let cache = UrlCache::new(pdf.clone(), &resources, now);
+
+        let cached = cache
+            .resources(&pdf, now + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(cached[0].url, resources[0].url);
+        assert_eq!(cached[0].content_id.as_deref(), Some("1"));
+        assert_eq!(cached[0].chapter_name.as_deref(), Some("第一周"));
+        assert!(cache.resources(&pptx, now).is_none());
+        assert!(cache.resources(&pdf, now + UrlCache::TTL).is_none());
+    }
+}